@@ -0,0 +1,110 @@
+//! Converts a 2 MiB flash image read from a file, serially and with every worker reading through
+//! a file handle of its own. Run with `cargo bench --features parallel`
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use elf2uf2_rs::{
+    boards::RP2040,
+    elf::{
+        Elf32Header, Elf32PhEntry, ELFCLASS32, ELFDATA2LSB, ELF_MAGIC, EM_ARM, ET_EXEC, PT_LOAD,
+    },
+    elf2uf2, elf2uf2_parallel,
+    progress::NoProgress,
+    uf2::Uf2Block,
+    ConversionOptions, ReadSeek,
+};
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufReader},
+    mem,
+    path::Path,
+};
+use zerocopy::IntoBytes;
+
+const IMAGE_SIZE: usize = 2 * 1024 * 1024;
+const FLASH_START: u32 = 0x10000000;
+
+/// A flash ELF of a single segment, starting with the boot2 stage of hello_usb so that it
+/// converts without warnings
+fn flash_elf() -> Vec<u8> {
+    let uf2 = include_bytes!("../hello_usb.uf2");
+    let first_block = Uf2Block::from_bytes(uf2[..512].try_into().unwrap()).unwrap();
+
+    let mut image: Vec<u8> = (0..IMAGE_SIZE).map(|i| (i * 7) as u8).collect();
+    image[..256].copy_from_slice(&first_block.data[..256]);
+
+    let mut header = Elf32Header::default();
+    header.common.magic = ELF_MAGIC;
+    header.common.arch_class = ELFCLASS32;
+    header.common.endianness = ELFDATA2LSB;
+    header.common.version = 1;
+    header.common.version2 = 1;
+    header.common.typ = ET_EXEC;
+    header.common.machine = EM_ARM;
+    header.entry = FLASH_START + 0x101;
+    header.ph_offset = mem::size_of::<Elf32Header>() as u32;
+    header.eh_size = mem::size_of::<Elf32Header>() as u16;
+    header.ph_entry_size = mem::size_of::<Elf32PhEntry>() as u16;
+    header.ph_num = 1;
+
+    let mut segment = Elf32PhEntry {
+        typ: PT_LOAD,
+        offset: 0x100,
+        vaddr: FLASH_START,
+        paddr: FLASH_START,
+        filez: IMAGE_SIZE as u32,
+        memsz: IMAGE_SIZE as u32,
+        align: 0x100,
+        ..Default::default()
+    };
+
+    let mut elf = header.as_mut_bytes().to_vec();
+    elf.extend_from_slice(segment.as_mut_bytes());
+    elf.resize(0x100, 0);
+    elf.extend_from_slice(&image);
+    elf
+}
+
+fn realize(c: &mut Criterion) {
+    let path = env::temp_dir().join(format!("elf2uf2-bench-{}.elf", std::process::id()));
+    fs::write(&path, flash_elf()).unwrap();
+    let open = |path: &Path| File::open(path).map(BufReader::new);
+
+    let mut group = c.benchmark_group("realize 2 MiB");
+    group.sample_size(20);
+
+    group.bench_function("serial", |b| {
+        b.iter(|| {
+            elf2uf2(
+                open(&path).unwrap(),
+                io::sink(),
+                &mut NoProgress,
+                &RP2040,
+                &ConversionOptions::default(),
+            )
+            .unwrap()
+        })
+    });
+
+    group.bench_function("parallel", |b| {
+        let open_worker = || -> io::Result<Box<dyn ReadSeek>> { Ok(Box::new(open(&path)?)) };
+        b.iter(|| {
+            elf2uf2_parallel(
+                open(&path).unwrap(),
+                &open_worker,
+                io::sink(),
+                &mut NoProgress,
+                &RP2040,
+                &ConversionOptions::default(),
+                &|_| Ok(()),
+            )
+            .unwrap()
+        })
+    });
+
+    group.finish();
+    fs::remove_file(&path).unwrap();
+}
+
+criterion_group!(benches, realize);
+criterion_main!(benches);
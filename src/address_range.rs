@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum AddressRangeType {
     /// May have contents
@@ -19,6 +21,35 @@ impl AddressRange {
     pub const fn new(from: u32, to: u32, typ: AddressRangeType) -> Self {
         Self { typ, to, from }
     }
+
+    /// Ranges are half open, `from` is included and `to` is not
+    pub const fn contains(&self, addr: u32) -> bool {
+        self.from <= addr && addr < self.to
+    }
+
+    /// True if the whole of `addr..addr + size` lies within the range
+    pub const fn contains_span(&self, addr: u32, size: u32) -> bool {
+        self.from <= addr && (addr as u64 + size as u64) <= self.to as u64
+    }
+
+    /// Ranges that only touch do not overlap
+    pub const fn overlaps(&self, other: &AddressRange) -> bool {
+        self.from < other.to && other.from < self.to
+    }
+
+    pub const fn len(&self) -> u32 {
+        self.to.saturating_sub(self.from)
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Display for AddressRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} {:#08x}->{:#08x}", self.typ, self.from, self.to)
+    }
 }
 
 impl Default for AddressRange {
@@ -58,3 +89,83 @@ pub const RP2040_ADDRESS_RANGES_RAM: &[AddressRange] = &[
     AddressRange::new(XIP_SRAM_START, XIP_SRAM_END, AddressRangeType::Contents),
     AddressRange::new(ROM_START, ROM_END, AddressRangeType::Ignore), // for now we ignore the bootrom if present
 ];
+
+/// Every address range known for the RP2040, those of flash binaries first. Main RAM is in both
+pub fn rp2040_address_ranges() -> impl Iterator<Item = &'static AddressRange> {
+    RP2040_ADDRESS_RANGES_FLASH
+        .iter()
+        .chain(RP2040_ADDRESS_RANGES_RAM)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn range_bounds() {
+        let range = AddressRange::new(0x1000, 0x2000, AddressRangeType::Contents);
+
+        assert!(!range.contains(0xfff));
+        assert!(range.contains(0x1000));
+        assert!(range.contains(0x1fff));
+        assert!(!range.contains(0x2000));
+
+        assert!(range.contains_span(0x1000, 0x1000));
+        assert!(range.contains_span(0x2000, 0));
+        assert!(!range.contains_span(0x1fff, 2));
+        assert!(!range.contains_span(0xfff, 1));
+        assert!(
+            !AddressRange::new(0, u32::MAX, AddressRangeType::Contents).contains_span(u32::MAX, 1)
+        );
+
+        assert_eq!(range.len(), 0x1000);
+        assert!(!range.is_empty());
+        let empty = AddressRange::new(0x1000, 0x1000, AddressRangeType::Contents);
+        assert_eq!(empty.len(), 0);
+        assert!(empty.is_empty());
+        assert!(!empty.contains(0x1000));
+        assert!(AddressRange::new(0x2000, 0x1000, AddressRangeType::Contents).is_empty());
+
+        let before = AddressRange::new(0x800, 0x1000, AddressRangeType::Contents);
+        let after = AddressRange::new(0x2000, 0x3000, AddressRangeType::Contents);
+        assert!(!range.overlaps(&before) && !before.overlaps(&range));
+        assert!(!range.overlaps(&after) && !after.overlaps(&range));
+        let straddling = AddressRange::new(0x1fff, 0x2001, AddressRangeType::Contents);
+        assert!(range.overlaps(&straddling) && straddling.overlaps(&range));
+        assert!(!range.overlaps(&empty));
+
+        assert_eq!(
+            AddressRange::new(FLASH_START, FLASH_END, AddressRangeType::NoContents).to_string(),
+            "NoContents 0x10000000->0x15000000"
+        );
+        assert_eq!(
+            AddressRange::new(ROM_START, ROM_END, AddressRangeType::Ignore).to_string(),
+            "Ignore 0x000000->0x004000"
+        );
+    }
+
+    #[test]
+    pub fn rp2040_ranges() {
+        let ranges: Vec<_> = rp2040_address_ranges().collect();
+        assert_eq!(ranges.len(), 6);
+        assert_eq!(
+            ranges[0].to_string(),
+            RP2040_ADDRESS_RANGES_FLASH[0].to_string()
+        );
+        assert_eq!(
+            ranges[3].to_string(),
+            RP2040_ADDRESS_RANGES_RAM[0].to_string()
+        );
+
+        // Flash is only writable by flash binaries, the bootrom by neither
+        let contents_at = |addr| {
+            rp2040_address_ranges()
+                .filter(|range| range.contains(addr) && range.typ == AddressRangeType::Contents)
+                .count()
+        };
+        assert_eq!(contents_at(FLASH_START), 1);
+        assert_eq!(contents_at(MAIN_RAM_START), 1);
+        assert_eq!(contents_at(XIP_SRAM_START), 1);
+        assert_eq!(contents_at(ROM_START), 0);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::address_range::{AddressRange, FLASH_SECTOR_ERASE_SIZE};
+use crate::elf::PAGE_SIZE;
+use std::{error::Error, ops::Range};
+
+mod nrf52840;
+mod rp2040;
+mod rp2350;
+mod teensy4;
+
+pub use nrf52840::Nrf52840;
+pub use rp2040::RP2040;
+pub use rp2350::RP2350;
+pub use teensy4::Teensy4;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct UsbDevice {
+    pub bus_number: u8,
+    pub address: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+}
+
+/// Fixed addresses the conversion checks images against. Unknown locations are `None`
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct AddressLocations {
+    pub flash_start: Option<u32>,
+    pub main_ram_start: Option<u32>,
+    pub main_ram_end: Option<u32>,
+    pub xip_sram_start: Option<u32>,
+    pub xip_sram_end: Option<u32>,
+    pub rom_start: Option<u32>,
+    pub rom_end: Option<u32>,
+}
+
+impl AddressLocations {
+    /// Main RAM, or `None` if the board has none
+    pub fn main_ram(&self) -> Result<Option<Range<u32>>, Box<dyn Error>> {
+        bounds("main RAM", self.main_ram_start, self.main_ram_end)
+    }
+
+    /// XIP SRAM, or `None` if the board has none
+    pub fn xip_sram(&self) -> Result<Option<Range<u32>>, Box<dyn Error>> {
+        bounds("XIP SRAM", self.xip_sram_start, self.xip_sram_end)
+    }
+
+    /// The bootrom, or `None` if the board has none
+    pub fn rom(&self) -> Result<Option<Range<u32>>, Box<dyn Error>> {
+        bounds("the bootrom", self.rom_start, self.rom_end)
+    }
+}
+
+fn bounds(
+    name: &str,
+    start: Option<u32>,
+    end: Option<u32>,
+) -> Result<Option<Range<u32>>, Box<dyn Error>> {
+    match (start, end) {
+        (Some(start), Some(end)) => Ok(Some(start..end)),
+        (None, None) => Ok(None),
+        (Some(_), None) => {
+            Err(format!("The board defines where {name} starts but not where it ends").into())
+        }
+        (None, Some(_)) => {
+            Err(format!("The board defines where {name} ends but not where it starts").into())
+        }
+    }
+}
+
+pub trait BoardInfo {
+    fn name(&self) -> &str;
+
+    /// True if the device is this board in its UF2 bootloader mode
+    fn is_device_board(&self, _device: &UsbDevice) -> bool {
+        false
+    }
+
+    /// True if a mounted UF2 drive with this `Board-ID` in its INFO_UF2.TXT is this board
+    fn is_uf2_board_id(&self, _board_id: &str) -> bool {
+        false
+    }
+
+    fn family_id(&self) -> u32;
+
+    /// True if the bootrom only boots flash images with an IMAGE_DEF block near their start
+    fn requires_image_def(&self) -> bool {
+        false
+    }
+
+    /// Payload bytes per UF2 block. At most 476, the size of the UF2 data region
+    fn page_size(&self) -> u32 {
+        PAGE_SIZE
+    }
+
+    /// Bytes erased together by the bootloader. Flash images are padded to whole sectors
+    fn flash_sector_erase_size(&self) -> u32 {
+        FLASH_SECTOR_ERASE_SIZE
+    }
+
+    /// Largest flash the board can have, from the start of its flash ranges. `None` when the
+    /// flash ranges already end there
+    fn flash_size(&self) -> Option<u32> {
+        None
+    }
+
+    /// Valid ranges when the entry point is in flash
+    fn address_ranges_flash(&self) -> &[AddressRange];
+
+    /// Valid ranges when the entry point is in RAM
+    fn address_ranges_ram(&self) -> &[AddressRange];
+
+    fn address_locations(&self) -> AddressLocations;
+}
+
+/// Everything the conversion needs to know about a board
+#[derive(Copy, Clone, Debug)]
+pub struct ConversionParams<'a> {
+    pub family_id: u32,
+    pub page_size: u32,
+    pub erase_size: u32,
+    pub flash_size: Option<u32>,
+    pub flash_ranges: &'a [AddressRange],
+    pub ram_ranges: &'a [AddressRange],
+    pub locations: AddressLocations,
+}
+
+impl<'a> From<&'a dyn BoardInfo> for ConversionParams<'a> {
+    fn from(board: &'a dyn BoardInfo) -> Self {
+        Self {
+            family_id: board.family_id(),
+            page_size: board.page_size(),
+            erase_size: board.flash_sector_erase_size(),
+            flash_size: board.flash_size(),
+            flash_ranges: board.address_ranges_flash(),
+            ram_ranges: board.address_ranges_ram(),
+            locations: board.address_locations(),
+        }
+    }
+}
+
+/// The boards to convert for. The default registry holds the boards built into elf2uf2, more
+/// can be registered after them
+pub struct BoardRegistry {
+    boards: Vec<Box<dyn BoardInfo>>,
+}
+
+impl BoardRegistry {
+    pub fn register(&mut self, board: Box<dyn BoardInfo>) {
+        self.boards.push(board);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn BoardInfo> {
+        self.boards.iter().map(|board| &**board)
+    }
+
+    /// The first registered board with this name, in any case
+    pub fn find_by_name(&self, name: &str) -> Option<&dyn BoardInfo> {
+        self.position_by_name(name).map(|i| &*self.boards[i])
+    }
+
+    /// Takes the board `find_by_name` finds out of the registry
+    pub fn take_by_name(&mut self, name: &str) -> Option<Box<dyn BoardInfo>> {
+        self.position_by_name(name).map(|i| self.boards.remove(i))
+    }
+
+    fn position_by_name(&self, name: &str) -> Option<usize> {
+        self.iter()
+            .position(|board| board.name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl Default for BoardRegistry {
+    fn default() -> Self {
+        Self {
+            boards: vec![
+                Box::new(RP2040),
+                Box::new(RP2350),
+                Box::new(Teensy4),
+                Box::new(Nrf52840),
+            ],
+        }
+    }
+}
+
+impl IntoIterator for BoardRegistry {
+    type Item = Box<dyn BoardInfo>;
+    type IntoIter = BoardIter;
+
+    fn into_iter(self) -> BoardIter {
+        BoardIter {
+            boards: self.boards.into_iter(),
+        }
+    }
+}
+
+/// Iterates the boards of a registry, `new` those built into elf2uf2
+pub struct BoardIter {
+    boards: std::vec::IntoIter<Box<dyn BoardInfo>>,
+}
+
+impl BoardIter {
+    pub fn new() -> Self {
+        BoardRegistry::default().into_iter()
+    }
+
+    /// The board converting for `family_id`. Should several boards share it, the first in
+    /// iteration order is found
+    pub fn find_by_family_id(family_id: u32) -> Option<Box<dyn BoardInfo>> {
+        Self::new().find(|board| board.family_id() == family_id)
+    }
+}
+
+impl Default for BoardIter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Iterator for BoardIter {
+    type Item = Box<dyn BoardInfo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.boards.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TestBoard;
+
+    #[test]
+    fn partial_locations() {
+        let locations = AddressLocations {
+            main_ram_start: Some(0x2000_0000),
+            xip_sram_end: Some(0x1500_4000),
+            ..Default::default()
+        };
+        assert!(locations.main_ram().is_err());
+        assert!(locations.xip_sram().is_err());
+
+        let locations = AddressLocations {
+            main_ram_start: Some(0x2000_0000),
+            main_ram_end: Some(0x2004_2000),
+            ..Default::default()
+        };
+        assert_eq!(
+            locations.main_ram().unwrap(),
+            Some(0x2000_0000..0x2004_2000)
+        );
+        assert_eq!(locations.xip_sram().unwrap(), None);
+    }
+
+    #[test]
+    fn board_by_family_id() {
+        for board in BoardIter::new() {
+            let found = BoardIter::find_by_family_id(board.family_id()).unwrap();
+            assert_eq!(found.name(), board.name());
+        }
+        assert!(BoardIter::find_by_family_id(0).is_none());
+    }
+
+    #[test]
+    fn device_boards() {
+        let device = |vendor_id, product_id| UsbDevice {
+            bus_number: 1,
+            address: 2,
+            vendor_id,
+            product_id,
+        };
+        let detect = |vendor_id, product_id| {
+            BoardIter::new()
+                .find(|board| board.is_device_board(&device(vendor_id, product_id)))
+                .map(|board| board.name().to_string())
+        };
+
+        assert_eq!(detect(0x2e8a, 0x0003).as_deref(), Some("rp2040"));
+        assert_eq!(detect(0x2e8a, 0x000f).as_deref(), Some("rp2350"));
+
+        // A Pico running its application, and other vendors' products with the same ids
+        assert_eq!(detect(0x2e8a, 0x000a), None);
+        assert_eq!(detect(0x1234, 0x0003), None);
+        assert_eq!(detect(0x1234, 0x000f), None);
+    }
+
+    #[test]
+    fn registered_board() {
+        let mut registry = BoardRegistry::default();
+        assert!(registry.find_by_name("fake").is_none());
+        registry.register(Box::new(TestBoard {
+            name: "fake",
+            family_id: 0x12345678,
+            flash_ranges: &[],
+            ram_ranges: &[],
+            locations: AddressLocations::default(),
+            ..Default::default()
+        }));
+
+        let board = registry.find_by_name("Fake").unwrap();
+        assert_eq!(board.family_id(), 0x12345678);
+        assert_eq!(registry.find_by_name("rp2040").unwrap().name(), "rp2040");
+
+        let mut taken = BoardRegistry::default();
+        assert_eq!(taken.take_by_name("RP2350").unwrap().name(), "rp2350");
+        assert!(taken.find_by_name("rp2350").is_none());
+        assert!(taken.take_by_name("rp2350").is_none());
+
+        let names: Vec<String> = registry
+            .into_iter()
+            .map(|board| board.name().to_string())
+            .collect();
+        assert_eq!(names.last().unwrap(), "fake");
+        assert_eq!(names.len(), BoardIter::new().count() + 1);
+    }
+}
@@ -0,0 +1,55 @@
+use super::{AddressLocations, BoardInfo};
+use crate::address_range::{AddressRange, AddressRangeType};
+
+pub const NRF52840_FAMILY_ID: u32 = 0xada52840;
+
+/// Internal flash, the MBR and SoftDevice at its start and the bootloader at its end are refused
+/// by the bootloader rather than here
+pub const NRF52840_FLASH_START: u32 = 0x00000000;
+pub const NRF52840_FLASH_END: u32 = 0x00100000;
+pub const NRF52840_RAM_START: u32 = 0x20000000;
+pub const NRF52840_RAM_END: u32 = 0x20040000;
+
+pub const NRF52840_ADDRESS_RANGES_FLASH: &[AddressRange] = &[
+    AddressRange::new(
+        NRF52840_FLASH_START,
+        NRF52840_FLASH_END,
+        AddressRangeType::Contents,
+    ),
+    AddressRange::new(
+        NRF52840_RAM_START,
+        NRF52840_RAM_END,
+        AddressRangeType::NoContents,
+    ),
+];
+
+/// nRF52840 boards with the Adafruit nRF52 UF2 bootloader, such as the Circuit Playground
+/// Bluefruit. The bootloader only writes flash, so there are no RAM binaries
+pub struct Nrf52840;
+
+impl BoardInfo for Nrf52840 {
+    fn name(&self) -> &str {
+        "nrf52840"
+    }
+
+    /// The bootloader names the board after the chip, e.g. nRF52840-CircuitPlayground-revD
+    fn is_uf2_board_id(&self, board_id: &str) -> bool {
+        board_id.starts_with("nRF52840-")
+    }
+
+    fn family_id(&self) -> u32 {
+        NRF52840_FAMILY_ID
+    }
+
+    fn address_ranges_flash(&self) -> &[AddressRange] {
+        NRF52840_ADDRESS_RANGES_FLASH
+    }
+
+    fn address_ranges_ram(&self) -> &[AddressRange] {
+        &[]
+    }
+
+    fn address_locations(&self) -> AddressLocations {
+        AddressLocations::default()
+    }
+}
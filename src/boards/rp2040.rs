@@ -0,0 +1,53 @@
+use super::{AddressLocations, BoardInfo, UsbDevice};
+use crate::{
+    address_range::{
+        AddressRange, FLASH_START, MAIN_RAM_END, MAIN_RAM_START, ROM_END, ROM_START,
+        RP2040_ADDRESS_RANGES_FLASH, RP2040_ADDRESS_RANGES_RAM, XIP_SRAM_END, XIP_SRAM_START,
+    },
+    uf2::RP2040_FAMILY_ID,
+};
+
+pub struct RP2040;
+
+impl BoardInfo for RP2040 {
+    fn name(&self) -> &str {
+        "rp2040"
+    }
+
+    fn is_device_board(&self, device: &UsbDevice) -> bool {
+        device.vendor_id == 0x2e8a && device.product_id == 0x0003
+    }
+
+    fn is_uf2_board_id(&self, board_id: &str) -> bool {
+        board_id == "RPI-RP2"
+    }
+
+    fn family_id(&self) -> u32 {
+        RP2040_FAMILY_ID
+    }
+
+    /// The flash window reaches well past the 16 MiB the QSPI interface addresses
+    fn flash_size(&self) -> Option<u32> {
+        Some(16 * 1024 * 1024)
+    }
+
+    fn address_ranges_flash(&self) -> &[AddressRange] {
+        RP2040_ADDRESS_RANGES_FLASH
+    }
+
+    fn address_ranges_ram(&self) -> &[AddressRange] {
+        RP2040_ADDRESS_RANGES_RAM
+    }
+
+    fn address_locations(&self) -> AddressLocations {
+        AddressLocations {
+            flash_start: Some(FLASH_START),
+            main_ram_start: Some(MAIN_RAM_START),
+            main_ram_end: Some(MAIN_RAM_END),
+            xip_sram_start: Some(XIP_SRAM_START),
+            xip_sram_end: Some(XIP_SRAM_END),
+            rom_start: Some(ROM_START),
+            rom_end: Some(ROM_END),
+        }
+    }
+}
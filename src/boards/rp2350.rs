@@ -0,0 +1,90 @@
+use super::{AddressLocations, BoardInfo, UsbDevice};
+use crate::{
+    address_range::{AddressRange, AddressRangeType},
+    uf2::RP2350_ARM_S_FAMILY_ID,
+};
+
+pub const RP2350_FLASH_START: u32 = 0x10000000;
+/// The 32 MiB XIP window covers both 16 MiB QMI chip selects, CS1 starts at 0x11000000.
+/// Partitions can put an image at any offset in it, so the whole window is accepted
+pub const RP2350_FLASH_END: u32 = 0x12000000;
+pub const RP2350_XIP_SRAM_START: u32 = 0x13ffc000;
+pub const RP2350_XIP_SRAM_END: u32 = 0x14000000;
+pub const RP2350_MAIN_RAM_START: u32 = 0x20000000;
+pub const RP2350_MAIN_RAM_END: u32 = 0x20082000;
+pub const RP2350_ROM_START: u32 = 0x00000000;
+pub const RP2350_ROM_END: u32 = 0x00008000;
+
+pub const RP2350_ADDRESS_RANGES_FLASH: &[AddressRange] = &[
+    AddressRange::new(
+        RP2350_FLASH_START,
+        RP2350_FLASH_END,
+        AddressRangeType::Contents,
+    ),
+    AddressRange::new(
+        RP2350_MAIN_RAM_START,
+        RP2350_MAIN_RAM_END,
+        AddressRangeType::NoContents,
+    ),
+];
+
+pub const RP2350_ADDRESS_RANGES_RAM: &[AddressRange] = &[
+    AddressRange::new(
+        RP2350_MAIN_RAM_START,
+        RP2350_MAIN_RAM_END,
+        AddressRangeType::Contents,
+    ),
+    AddressRange::new(
+        RP2350_XIP_SRAM_START,
+        RP2350_XIP_SRAM_END,
+        AddressRangeType::Contents,
+    ),
+    AddressRange::new(RP2350_ROM_START, RP2350_ROM_END, AddressRangeType::Ignore),
+];
+
+/// RP2350 images for the Arm cores in secure mode
+pub struct RP2350;
+
+impl BoardInfo for RP2350 {
+    fn name(&self) -> &str {
+        "rp2350"
+    }
+
+    fn is_device_board(&self, device: &UsbDevice) -> bool {
+        device.vendor_id == 0x2e8a && device.product_id == 0x000f
+    }
+
+    fn is_uf2_board_id(&self, board_id: &str) -> bool {
+        board_id.starts_with("RP2350")
+    }
+
+    fn family_id(&self) -> u32 {
+        RP2350_ARM_S_FAMILY_ID
+    }
+
+    fn requires_image_def(&self) -> bool {
+        true
+    }
+
+    fn address_ranges_flash(&self) -> &[AddressRange] {
+        RP2350_ADDRESS_RANGES_FLASH
+    }
+
+    fn address_ranges_ram(&self) -> &[AddressRange] {
+        RP2350_ADDRESS_RANGES_RAM
+    }
+
+    /// The RP2350 bootrom looks for an image definition instead of a boot2 stage, so there is no
+    /// flash start to check
+    fn address_locations(&self) -> AddressLocations {
+        AddressLocations {
+            flash_start: None,
+            main_ram_start: Some(RP2350_MAIN_RAM_START),
+            main_ram_end: Some(RP2350_MAIN_RAM_END),
+            xip_sram_start: Some(RP2350_XIP_SRAM_START),
+            xip_sram_end: Some(RP2350_XIP_SRAM_END),
+            rom_start: Some(RP2350_ROM_START),
+            rom_end: Some(RP2350_ROM_END),
+        }
+    }
+}
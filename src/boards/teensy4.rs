@@ -0,0 +1,63 @@
+use super::{AddressLocations, BoardInfo};
+use crate::address_range::{AddressRange, AddressRangeType};
+
+pub const TEENSY4_FAMILY_ID: u32 = 0x4fb2d5bd;
+
+/// FlexSPI flash, 2 MiB on the Teensy 4.0 and 8 MiB on the Teensy 4.1
+pub const TEENSY4_FLASH_START: u32 = 0x60000000;
+pub const TEENSY4_FLASH_END: u32 = 0x60800000;
+pub const TEENSY4_ITCM_START: u32 = 0x00000000;
+pub const TEENSY4_ITCM_END: u32 = 0x00080000;
+pub const TEENSY4_DTCM_START: u32 = 0x20000000;
+pub const TEENSY4_DTCM_END: u32 = 0x20080000;
+pub const TEENSY4_OCRAM_START: u32 = 0x20200000;
+pub const TEENSY4_OCRAM_END: u32 = 0x20280000;
+
+pub const TEENSY4_ADDRESS_RANGES_FLASH: &[AddressRange] = &[
+    AddressRange::new(
+        TEENSY4_FLASH_START,
+        TEENSY4_FLASH_END,
+        AddressRangeType::Contents,
+    ),
+    AddressRange::new(
+        TEENSY4_ITCM_START,
+        TEENSY4_ITCM_END,
+        AddressRangeType::NoContents,
+    ),
+    AddressRange::new(
+        TEENSY4_DTCM_START,
+        TEENSY4_DTCM_END,
+        AddressRangeType::NoContents,
+    ),
+    AddressRange::new(
+        TEENSY4_OCRAM_START,
+        TEENSY4_OCRAM_END,
+        AddressRangeType::NoContents,
+    ),
+];
+
+/// Teensy 4.0 and 4.1 (i.MX RT1062) with the TeensyUF2 bootloader. The bootloader only writes
+/// flash, so there are no RAM binaries and no boot2 stage
+pub struct Teensy4;
+
+impl BoardInfo for Teensy4 {
+    fn name(&self) -> &str {
+        "teensy4"
+    }
+
+    fn family_id(&self) -> u32 {
+        TEENSY4_FAMILY_ID
+    }
+
+    fn address_ranges_flash(&self) -> &[AddressRange] {
+        TEENSY4_ADDRESS_RANGES_FLASH
+    }
+
+    fn address_ranges_ram(&self) -> &[AddressRange] {
+        &[]
+    }
+
+    fn address_locations(&self) -> AddressLocations {
+        AddressLocations::default()
+    }
+}
@@ -0,0 +1,29 @@
+use crate::crc::crc32_mpeg2;
+
+/// boot2 occupies the first 256 bytes of flash, the last 4 of which are a CRC over the first 252
+pub const BOOT2_SIZE: usize = 256;
+
+/// Checks that the page at the start of flash is a boot2 stage the RP2040 bootrom will accept
+pub fn is_valid_boot2(page: &[u8]) -> bool {
+    if page.len() < BOOT2_SIZE {
+        return false;
+    }
+
+    let (code, crc) = page[..BOOT2_SIZE].split_at(BOOT2_SIZE - 4);
+    let crc = u32::from_le_bytes(crc.try_into().unwrap());
+
+    crc32_mpeg2(code) == crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn hello_usb_has_boot2() {
+        let uf2 = include_bytes!("../hello_usb.uf2");
+        // The first block targets the start of flash, its payload follows the 32 byte header
+        assert!(is_valid_boot2(&uf2[32..32 + BOOT2_SIZE]));
+        assert!(!is_valid_boot2(&[0; BOOT2_SIZE]));
+    }
+}
@@ -0,0 +1,68 @@
+/// CRC-32/MPEG-2: polynomial 0x04c11db7, initial value 0xffffffff, no reflection and no final xor.
+/// This is the variant the RP2040 bootrom uses to check boot2.
+pub fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffff_u32;
+
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x80000000 != 0 {
+                (crc << 1) ^ 0x04c11db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+/// CRC-32 as used by zlib and Ethernet: reflected polynomial 0xedb88320, initial value and final xor
+/// 0xffffffff
+pub struct Crc32 {
+    crc: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self { crc: 0xffffffff }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.crc ^= *byte as u32;
+            for _ in 0..8 {
+                self.crc = if self.crc & 1 != 0 {
+                    (self.crc >> 1) ^ 0xedb88320
+                } else {
+                    self.crc >> 1
+                };
+            }
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.crc
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn check_value() {
+        assert_eq!(crc32_mpeg2(b"123456789"), 0x0376e6e7);
+
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xcbf43926);
+    }
+}
@@ -1,25 +1,37 @@
 use crate::{
-    address_range::{self, AddressRange, RP2040_ADDRESS_RANGES_FLASH, RP2040_ADDRESS_RANGES_RAM},
-    Opts,
+    address_range::{self, AddressRange},
+    boards::ConversionParams,
+    crc::Crc32,
+    Verbosity,
 };
 use assert_into::AssertInto;
 use std::{
     cmp::min,
-    collections::BTreeMap,
+    collections::{btree_map, BTreeMap},
     error::Error,
-    io::{Read, Seek, SeekFrom},
+    io::{self, Read, Seek, SeekFrom},
     mem,
+    ops::Range,
 };
 use zerocopy::{FromBytes, IntoBytes};
 
-const ELF_MAGIC: u32 = 0x464c457f;
-const PT_LOAD: u32 = 0x00000001;
+pub const ELF_MAGIC: u32 = 0x464c457f;
+pub const ELFCLASS32: u8 = 1;
+pub const ELFCLASS64: u8 = 2;
+pub const PT_LOAD: u32 = 0x00000001;
+const ET_REL: u16 = 1;
+pub const ET_EXEC: u16 = 2;
+
+pub const ELFDATA2LSB: u8 = 1;
+pub const ELFDATA2MSB: u8 = 2;
+
+pub const EM_ARM: u16 = 40;
 
 pub const LOG2_PAGE_SIZE: u32 = 8;
 pub const PAGE_SIZE: u32 = 1 << LOG2_PAGE_SIZE;
 
 #[allow(unused)]
-#[repr(packed)]
+#[repr(C, packed)]
 #[derive(IntoBytes, Copy, Clone, Default, Debug, FromBytes)]
 pub struct ElfHeader {
     pub magic: u32,
@@ -35,7 +47,7 @@ pub struct ElfHeader {
 }
 
 #[allow(unused)]
-#[repr(packed)]
+#[repr(C, packed)]
 #[derive(IntoBytes, Copy, Clone, Default, Debug, FromBytes)]
 pub struct Elf32Header {
     pub common: ElfHeader,
@@ -53,46 +65,157 @@ pub struct Elf32Header {
 
 impl Elf32Header {
     // read_and_check_elf32_header
-    pub(crate) fn from_read(input: &mut impl Read) -> Result<Self, Box<dyn Error>> {
-        let mut eh = Elf32Header::default();
+    /// Reads the header of a 32 or 64 bit ELF. A 64 bit header is narrowed to 32 bits, keeping its
+    /// class and sizes so the program headers are read as 64 bit too
+    pub fn from_read(input: &mut impl Read) -> Result<Self, Box<dyn Error>> {
+        let mut common = ElfHeader::default();
 
-        input.read_exact(eh.as_mut_bytes())?;
+        input
+            .read_exact(common.as_mut_bytes())
+            .map_err(|err| format!("Failed to read the ELF header: {err}"))?;
 
-        if eh.common.magic != ELF_MAGIC {
-            return Err("Not an ELF file".into());
+        if common.magic != ELF_MAGIC {
+            return Err(format!(
+                "Not an ELF file, it starts with {:02x?}",
+                common.magic.to_le_bytes()
+            )
+            .into());
         }
-        if eh.common.version != 1 || eh.common.version2 != 1 {
-            return Err("Unrecognized ELF version".into());
+        if common.endianness != ELFDATA2LSB && common.endianness != ELFDATA2MSB {
+            return Err(format!("Unrecognized ELF endianness {}", common.endianness).into());
         }
-        if eh.common.arch_class != 1 || eh.common.endianness != 1 {
-            return Err("Require 32 bit little-endian ELF".into());
+        let big_endian = common.endianness == ELFDATA2MSB;
+
+        let (eh, header_size) = match common.arch_class {
+            ELFCLASS32 => {
+                let mut eh = Elf32Header {
+                    common,
+                    ..Default::default()
+                };
+                input
+                    .read_exact(&mut eh.as_mut_bytes()[mem::size_of::<ElfHeader>()..])
+                    .map_err(|err| format!("Failed to read the ELF header: {err}"))?;
+                if big_endian {
+                    eh.swap_bytes();
+                }
+                (eh, mem::size_of::<Elf32Header>())
+            }
+            ELFCLASS64 => {
+                let mut eh = Elf64Header {
+                    common,
+                    ..Default::default()
+                };
+                input
+                    .read_exact(&mut eh.as_mut_bytes()[mem::size_of::<ElfHeader>()..])
+                    .map_err(|err| format!("Failed to read the ELF header: {err}"))?;
+                if big_endian {
+                    eh.swap_bytes();
+                }
+                (eh.narrow()?, mem::size_of::<Elf64Header>())
+            }
+            class => return Err(format!("Unrecognized ELF class {class}").into()),
+        };
+
+        let common = eh.common;
+        if common.version != 1 || common.version2 != 1 {
+            return Err(format!("Unrecognized ELF version {}/{}", common.version, {
+                common.version2
+            })
+            .into());
+        }
+        if usize::from(eh.eh_size) != header_size {
+            return Err(format!("Invalid ELF format, header size {}", { eh.eh_size }).into());
         }
-        if eh.eh_size != mem::size_of::<Elf32Header>().assert_into() {
-            return Err("Invalid ELF32 format".into());
+        if common.abi != 0 {
+            return Err(format!("Unrecognized ABI {}", common.abi).into());
         }
-        if eh.common.abi != 0 {
-            return Err("Unrecognized ABI".into());
+        if common.typ == ET_REL {
+            return Err(
+                "This is a relocatable object (.o), not a linked executable; link it first".into(),
+            );
         }
 
         Ok(eh)
     }
 
-    pub(crate) fn read_elf32_ph_entries(
+    /// Converts the multi-byte fields between big endian and native little endian. The ident
+    /// bytes, magic included, are the same either way
+    pub(crate) fn swap_bytes(&mut self) {
+        self.common.typ = self.common.typ.swap_bytes();
+        self.common.machine = self.common.machine.swap_bytes();
+        self.common.version2 = self.common.version2.swap_bytes();
+        self.entry = self.entry.swap_bytes();
+        self.ph_offset = self.ph_offset.swap_bytes();
+        self.sh_offset = self.sh_offset.swap_bytes();
+        self.flags = self.flags.swap_bytes();
+        self.eh_size = self.eh_size.swap_bytes();
+        self.ph_entry_size = self.ph_entry_size.swap_bytes();
+        self.ph_num = self.ph_num.swap_bytes();
+        self.sh_entry_size = self.sh_entry_size.swap_bytes();
+        self.sh_num = self.sh_num.swap_bytes();
+        self.sh_str_index = self.sh_str_index.swap_bytes();
+    }
+
+    /// True for ARM, whose Thumb entry points have the lowest bit set. RISC-V entry points are even
+    pub(crate) fn has_thumb_entry(&self) -> bool {
+        self.common.machine == EM_ARM
+    }
+
+    pub fn read_elf32_ph_entries(
         &self,
         input: &mut impl Read,
     ) -> Result<Vec<Elf32PhEntry>, Box<dyn Error>> {
+        if self.common.arch_class == ELFCLASS64 {
+            return self.read_elf64_ph_entries(input);
+        }
+
         if self.ph_entry_size != mem::size_of::<Elf32PhEntry>().assert_into() {
             return Err("Invalid ELF32 program header".into());
         }
 
         let mut entries: Vec<Elf32PhEntry> = (0..self.ph_num).map(|_| Default::default()).collect();
-        input.read_exact(entries.as_mut_slice().as_mut_bytes())?;
+        input
+            .read_exact(entries.as_mut_slice().as_mut_bytes())
+            .map_err(|err| format!("Failed to read the ELF program headers: {err}"))?;
+
+        if self.common.endianness == ELFDATA2MSB {
+            entries.iter_mut().for_each(Elf32PhEntry::swap_bytes);
+        }
 
         Ok(entries)
     }
 
+    fn read_elf64_ph_entries(
+        &self,
+        input: &mut impl Read,
+    ) -> Result<Vec<Elf32PhEntry>, Box<dyn Error>> {
+        if self.ph_entry_size != mem::size_of::<Elf64PhEntry>().assert_into() {
+            return Err("Invalid ELF64 program header".into());
+        }
+
+        let mut entries: Vec<Elf64PhEntry> = (0..self.ph_num).map(|_| Default::default()).collect();
+        input
+            .read_exact(entries.as_mut_slice().as_mut_bytes())
+            .map_err(|err| format!("Failed to read the ELF program headers: {err}"))?;
+
+        entries
+            .iter_mut()
+            .enumerate()
+            .map(|(i, entry)| {
+                if self.common.endianness == ELFDATA2MSB {
+                    entry.swap_bytes();
+                }
+                entry.narrow(i)
+            })
+            .collect()
+    }
+
     // "determine_binary_type"
-    pub(crate) fn is_ram_binary(&self, entries: &[Elf32PhEntry]) -> Option<bool> {
+    pub(crate) fn classify_entry(
+        &self,
+        entries: &[Elf32PhEntry],
+        params: &ConversionParams,
+    ) -> Option<EntryPoint> {
         for entry in entries {
             if entry.typ == PT_LOAD && entry.memsz > 0 {
                 let mapped_size = entry.filez.min(entry.memsz);
@@ -100,13 +223,17 @@ impl Elf32Header {
                     // We back-convert the entrypoint from a VADDR to a PADDR to see if it originates inflash, and if
                     // so call THAT a flash binary
                     if self.entry >= entry.vaddr && self.entry < entry.vaddr + mapped_size {
-                        let effective_entry = self.entry + entry.paddr - entry.vaddr;
-                        if RP2040_ADDRESS_RANGES_RAM.is_address_initialized(effective_entry) {
-                            return Some(true);
-                        } else if RP2040_ADDRESS_RANGES_FLASH
-                            .is_address_initialized(effective_entry)
-                        {
-                            return Some(false);
+                        let effective_entry = self.entry - entry.vaddr + entry.paddr;
+                        let classified = |ram_binary, ranges: &[AddressRange]| EntryPoint {
+                            entry: self.entry,
+                            effective_entry,
+                            ram_binary,
+                            range: *ranges.range_for(effective_entry).unwrap(),
+                        };
+                        if params.ram_ranges.is_address_initialized(effective_entry) {
+                            return Some(classified(true, params.ram_ranges));
+                        } else if params.flash_ranges.is_address_initialized(effective_entry) {
+                            return Some(classified(false, params.flash_ranges));
                         }
                     }
                 }
@@ -117,8 +244,20 @@ impl Elf32Header {
     }
 }
 
+/// How the entry point classifies a binary
+#[derive(Copy, Clone, Debug)]
+pub struct EntryPoint {
+    /// The entry point from the ELF header, a virtual address
+    pub entry: u32,
+    /// The entry point back-converted to a physical address
+    pub effective_entry: u32,
+    pub ram_binary: bool,
+    /// The range the effective entry point is in
+    pub range: AddressRange,
+}
+
 #[allow(unused)]
-#[repr(packed)]
+#[repr(C, packed)]
 #[derive(IntoBytes, Copy, Clone, Default, Debug, FromBytes)]
 pub struct Elf32PhEntry {
     pub typ: u32,
@@ -131,6 +270,284 @@ pub struct Elf32PhEntry {
     pub align: u32,
 }
 
+impl Elf32PhEntry {
+    /// Converts every field between big endian and native little endian
+    pub(crate) fn swap_bytes(&mut self) {
+        self.typ = self.typ.swap_bytes();
+        self.offset = self.offset.swap_bytes();
+        self.vaddr = self.vaddr.swap_bytes();
+        self.paddr = self.paddr.swap_bytes();
+        self.filez = self.filez.swap_bytes();
+        self.memsz = self.memsz.swap_bytes();
+        self.flags = self.flags.swap_bytes();
+        self.align = self.align.swap_bytes();
+    }
+}
+
+#[allow(unused)]
+#[repr(C, packed)]
+#[derive(IntoBytes, Copy, Clone, Default, Debug, FromBytes)]
+pub struct Elf64Header {
+    pub common: ElfHeader,
+    pub entry: u64,
+    pub ph_offset: u64,
+    pub sh_offset: u64,
+    pub flags: u32,
+    pub eh_size: u16,
+    pub ph_entry_size: u16,
+    pub ph_num: u16,
+    pub sh_entry_size: u16,
+    pub sh_num: u16,
+    pub sh_str_index: u16,
+}
+
+impl Elf64Header {
+    /// Converts the multi-byte fields between big endian and native little endian
+    pub(crate) fn swap_bytes(&mut self) {
+        self.common.typ = self.common.typ.swap_bytes();
+        self.common.machine = self.common.machine.swap_bytes();
+        self.common.version2 = self.common.version2.swap_bytes();
+        self.entry = self.entry.swap_bytes();
+        self.ph_offset = self.ph_offset.swap_bytes();
+        self.sh_offset = self.sh_offset.swap_bytes();
+        self.flags = self.flags.swap_bytes();
+        self.eh_size = self.eh_size.swap_bytes();
+        self.ph_entry_size = self.ph_entry_size.swap_bytes();
+        self.ph_num = self.ph_num.swap_bytes();
+        self.sh_entry_size = self.sh_entry_size.swap_bytes();
+        self.sh_num = self.sh_num.swap_bytes();
+        self.sh_str_index = self.sh_str_index.swap_bytes();
+    }
+
+    /// The same header with 32 bit addresses and offsets, or an error if any of them is past 4 GiB
+    pub(crate) fn narrow(&self) -> Result<Elf32Header, Box<dyn Error>> {
+        let narrow = |what: &str, value: u64| {
+            u32::try_from(value).map_err(|_| {
+                format!("The ELF {what} {value:#x} is past 4 GiB, UF2 only addresses 32 bits")
+            })
+        };
+
+        Ok(Elf32Header {
+            common: self.common,
+            entry: narrow("entry point", self.entry)?,
+            ph_offset: narrow("program header offset", self.ph_offset)?,
+            sh_offset: narrow("section header offset", self.sh_offset)?,
+            flags: self.flags,
+            eh_size: self.eh_size,
+            ph_entry_size: self.ph_entry_size,
+            ph_num: self.ph_num,
+            sh_entry_size: self.sh_entry_size,
+            sh_num: self.sh_num,
+            sh_str_index: self.sh_str_index,
+        })
+    }
+}
+
+#[allow(unused)]
+#[repr(C, packed)]
+#[derive(IntoBytes, Copy, Clone, Default, Debug, FromBytes)]
+pub struct Elf64PhEntry {
+    pub typ: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub filez: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+impl Elf64PhEntry {
+    /// Converts every field between big endian and native little endian
+    pub(crate) fn swap_bytes(&mut self) {
+        self.typ = self.typ.swap_bytes();
+        self.flags = self.flags.swap_bytes();
+        self.offset = self.offset.swap_bytes();
+        self.vaddr = self.vaddr.swap_bytes();
+        self.paddr = self.paddr.swap_bytes();
+        self.filez = self.filez.swap_bytes();
+        self.memsz = self.memsz.swap_bytes();
+        self.align = self.align.swap_bytes();
+    }
+
+    /// The same program header with 32 bit fields, or an error naming segment `index` if any of
+    /// them is past 4 GiB
+    pub(crate) fn narrow(&self, index: usize) -> Result<Elf32PhEntry, Box<dyn Error>> {
+        let narrow = |what: &str, value: u64| {
+            u32::try_from(value).map_err(|_| {
+                format!(
+                    "Segment {index} has {what} {value:#x}, past 4 GiB, UF2 only addresses 32 bits"
+                )
+            })
+        };
+
+        Ok(Elf32PhEntry {
+            typ: self.typ,
+            offset: narrow("file offset", self.offset)?,
+            vaddr: narrow("virtual address", self.vaddr)?,
+            paddr: narrow("physical address", self.paddr)?,
+            filez: narrow("file size", self.filez)?,
+            memsz: narrow("memory size", self.memsz)?,
+            flags: self.flags,
+            align: narrow("alignment", self.align)?,
+        })
+    }
+}
+
+/// Checks that the file contents of every loadable segment lie within the input
+pub fn check_elf32_ph_file_bounds(
+    entries: &[Elf32PhEntry],
+    file_len: u64,
+) -> Result<(), Box<dyn Error>> {
+    for (i, entry) in entries.iter().enumerate() {
+        let mapped_size = min(entry.filez, entry.memsz);
+
+        if entry.typ == PT_LOAD && mapped_size > 0 {
+            let end = entry.offset as u64 + mapped_size as u64;
+            if end > file_len {
+                #[allow(clippy::unnecessary_cast)]
+                return Err(format!(
+                    "Segment {} at file offset {:#08x} with size {:#08x} extends past the end of the file ({:#08x} bytes)",
+                    i, entry.offset as u32, mapped_size, file_len
+                )
+                .into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Describes every loadable segment whose address and file offset disagree modulo its alignment,
+/// a sign of a mis-linked image
+pub fn elf32_ph_alignment_problems(entries: &[Elf32PhEntry]) -> Vec<String> {
+    entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| {
+            entry.typ == PT_LOAD
+                && entry.align > 1
+                && entry.vaddr.wrapping_sub(entry.offset) % entry.align != 0
+        })
+        .map(|(i, entry)| {
+            format!(
+                "Segment {} at {:#08x} has file offset {:#08x}, which does not match its {:#x} byte alignment",
+                i,
+                { entry.vaddr },
+                { entry.offset },
+                { entry.align }
+            )
+        })
+        .collect()
+}
+
+/// Checks that every loadable segment ends below 4 GiB, so it fits in the 32 bit address space UF2
+/// blocks can target and its end is a 32 bit address too
+pub fn check_elf32_ph_address_bounds(entries: &[Elf32PhEntry]) -> Result<(), Box<dyn Error>> {
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.typ == PT_LOAD {
+            for (kind, addr) in [("physical", entry.paddr), ("virtual", entry.vaddr)] {
+                if u64::from(addr) + u64::from(entry.memsz) >= 1 << 32 {
+                    return Err(format!(
+                        "Segment {} at {kind} address {:#08x} with size {:#08x} reaches 4 GiB, UF2 only addresses 32 bits",
+                        i, addr, { entry.memsz }
+                    )
+                    .into());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Restricts the loadable segments to what lies inside any of the `include` ranges, or anywhere if there are none,
+/// and outside all of the `exclude` ranges. Segments crossing a boundary are split there
+pub fn filter_elf32_ph_entries(
+    entries: &[Elf32PhEntry],
+    include: &[Range<u32>],
+    exclude: &[Range<u32>],
+) -> Vec<Elf32PhEntry> {
+    let mut filtered = Vec::new();
+
+    for entry in entries {
+        if entry.typ != PT_LOAD || (include.is_empty() && exclude.is_empty()) {
+            filtered.push(*entry);
+            continue;
+        }
+
+        let start = entry.paddr;
+        let end = entry.paddr + entry.memsz;
+        let mut bounds: Vec<u32> = include
+            .iter()
+            .chain(exclude)
+            .flat_map(|range| [range.start, range.end])
+            .filter(|&addr| start < addr && addr < end)
+            .chain([start, end])
+            .collect();
+        bounds.sort_unstable();
+        bounds.dedup();
+
+        let wanted = |addr: u32| {
+            (include.is_empty() || include.iter().any(|range| range.contains(&addr)))
+                && !exclude.iter().any(|range| range.contains(&addr))
+        };
+
+        let mut pieces: Vec<Range<u32>> = Vec::new();
+        for piece in bounds.windows(2) {
+            if !wanted(piece[0]) {
+                continue;
+            }
+            match pieces.last_mut() {
+                Some(last) if last.end == piece[0] => last.end = piece[1],
+                _ => pieces.push(piece[0]..piece[1]),
+            }
+        }
+
+        for piece in pieces {
+            let skip = piece.start - start;
+            filtered.push(Elf32PhEntry {
+                offset: entry.offset + skip,
+                vaddr: entry.vaddr + skip,
+                paddr: piece.start,
+                filez: entry
+                    .filez
+                    .saturating_sub(skip)
+                    .min(piece.end - piece.start),
+                memsz: piece.end - piece.start,
+                ..*entry
+            });
+        }
+    }
+
+    filtered
+}
+
+/// Every range of `exclude` that overlaps the contents loadable segments have in the file, with
+/// the number of bytes of contents it overlaps
+pub fn excluded_contents(
+    entries: &[Elf32PhEntry],
+    exclude: &[Range<u32>],
+) -> Vec<(Range<u32>, u64)> {
+    exclude
+        .iter()
+        .filter_map(|range| {
+            let bytes: u64 = entries
+                .iter()
+                .filter(|entry| entry.typ == PT_LOAD)
+                .map(|entry| {
+                    let start = u64::from(entry.paddr);
+                    let end = start + u64::from(min(entry.filez, entry.memsz));
+                    let overlap_start = start.max(range.start.into());
+                    let overlap_end = end.min(range.end.into());
+                    overlap_end.saturating_sub(overlap_start)
+                })
+                .sum();
+            (bytes > 0).then(|| (range.clone(), bytes))
+        })
+        .collect()
+}
+
 #[derive(Copy, Clone, Debug, Default)]
 pub struct PageFragment {
     pub file_offset: u32,
@@ -138,15 +555,19 @@ pub struct PageFragment {
     pub bytes: u32,
 }
 
+/// Fragments of every page keyed by the page address
+pub type PageMap = BTreeMap<u32, Vec<PageFragment>>;
+
 pub fn realize_page(
     input: &mut (impl Read + Seek),
     fragments: &[PageFragment],
     buf: &mut [u8],
-) -> Result<(), Box<dyn Error>> {
-    assert!(buf.len() >= PAGE_SIZE.assert_into());
+    page_size: u32,
+) -> io::Result<()> {
+    assert!(buf.len() >= page_size.assert_into());
 
     for frag in fragments {
-        assert!(frag.page_offset < PAGE_SIZE && frag.page_offset + frag.bytes <= PAGE_SIZE);
+        assert!(frag.page_offset < page_size && frag.page_offset + frag.bytes <= page_size);
 
         input.seek(SeekFrom::Start(frag.file_offset.assert_into()))?;
 
@@ -158,11 +579,139 @@ pub fn realize_page(
     Ok(())
 }
 
+/// Seeks this far ahead are read past instead, a `BufReader` discards its buffer on every seek
+const FORWARD_SKIP_LIMIT: u64 = 4096;
+
+/// Keeps track of the position in a reader to avoid seeking it where it need not. A seek to where
+/// the reader already is does nothing, and a short seek ahead reads past the bytes in between.
+/// Fragments are realized in file order, so this takes most seeks out of a conversion
+pub struct ForwardReader<R> {
+    inner: R,
+    pos: Option<u64>,
+}
+
+impl<R> ForwardReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, pos: None }
+    }
+}
+
+impl<R: Read> Read for ForwardReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(pos) = &mut self.pos {
+            *pos += read as u64;
+        }
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for ForwardReader<R> {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        let target = match (to, self.pos) {
+            (SeekFrom::Start(target), _) => Some(target),
+            (SeekFrom::Current(delta), Some(pos)) => pos.checked_add_signed(delta),
+            _ => None,
+        };
+
+        if let (Some(target), Some(pos)) = (target, self.pos) {
+            if (pos..=pos + FORWARD_SKIP_LIMIT).contains(&target) {
+                self.pos = None;
+                let skipped = io::copy(&mut (&mut self.inner).take(target - pos), &mut io::sink())?;
+                self.pos = Some(pos + skipped);
+                // Past the end of the input only a real seek gets to the target
+                if pos + skipped == target {
+                    return Ok(target);
+                }
+            }
+        }
+
+        // The reader may have moved while skipping, so a relative seek is made absolute
+        self.pos = None;
+        let pos = self.inner.seek(target.map_or(to, SeekFrom::Start))?;
+        self.pos = Some(pos);
+        Ok(pos)
+    }
+}
+
+/// Pages of a page map with their bytes read from the input, bytes not covered by a fragment
+/// are zero. Each page is only read when it is reached
+pub struct RealizedPages<'a, R> {
+    input: &'a mut R,
+    pages: btree_map::Range<'a, u32, Vec<PageFragment>>,
+    page_size: u32,
+}
+
+impl<'a, R: Read + Seek> RealizedPages<'a, R> {
+    /// `pages` is a range of a page map, `..` for all of it
+    pub fn new(
+        input: &'a mut R,
+        pages: btree_map::Range<'a, u32, Vec<PageFragment>>,
+        page_size: u32,
+    ) -> Self {
+        Self {
+            input,
+            pages,
+            page_size,
+        }
+    }
+}
+
+impl<R: Read + Seek> Iterator for RealizedPages<'_, R> {
+    type Item = io::Result<(u32, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (addr, fragments) = self.pages.next()?;
+
+        let mut page = vec![0; self.page_size.assert_into()];
+        Some(realize_page(self.input, fragments, &mut page, self.page_size).map(|()| (*addr, page)))
+    }
+}
+
+/// CRC-32 of the image from the first page up to `end`. Bytes without contents read as
+/// `fill_byte`, the way padding is written, whether they are in a page or not
+pub fn image_crc32(
+    input: &mut (impl Read + Seek),
+    pages: &PageMap,
+    page_size: u32,
+    end: u32,
+    fill_byte: u8,
+) -> Result<u32, Box<dyn Error>> {
+    let mut crc = Crc32::new();
+    let mut buf = vec![0; page_size.assert_into()];
+
+    let fill = vec![fill_byte; page_size.assert_into()];
+    let fill_gap = |crc: &mut Crc32, mut len: u32| {
+        while len > 0 {
+            let n = min(len, page_size);
+            crc.update(&fill[..n.assert_into()]);
+            len -= n;
+        }
+    };
+
+    let Some(mut addr) = pages.keys().next().copied() else {
+        return Ok(crc.finish());
+    };
+
+    for (page_addr, fragments) in pages.range(..end) {
+        fill_gap(&mut crc, page_addr - addr);
+
+        buf.fill(fill_byte);
+        realize_page(input, fragments, &mut buf, page_size)?;
+
+        let len = min(page_size, end - page_addr);
+        crc.update(&buf[..len.assert_into()]);
+        addr = page_addr + len;
+    }
+
+    fill_gap(&mut crc, end.saturating_sub(addr));
+
+    Ok(crc.finish())
+}
+
 pub trait AddressRangesExt<'a>: IntoIterator<Item = &'a AddressRange> + Clone {
     fn range_for(&self, addr: u32) -> Option<&'a AddressRange> {
-        self.clone()
-            .into_iter()
-            .find(|r| r.from <= addr && r.to > addr)
+        self.clone().into_iter().find(|r| r.contains(addr))
     }
 
     fn is_address_initialized(&self, addr: u32) -> bool {
@@ -182,18 +731,19 @@ pub trait AddressRangesExt<'a>: IntoIterator<Item = &'a AddressRange> + Clone {
         vaddr: u32,
         size: u32,
         uninitialized: bool,
+        verbosity: Verbosity,
     ) -> Result<AddressRange, Box<dyn Error>> {
         for range in self.clone().into_iter() {
-            if range.from <= addr && range.to >= addr + size {
+            if range.contains_span(addr, size) {
                 if range.typ == address_range::AddressRangeType::NoContents && !uninitialized {
                     return Err(format!(
                         "ELF contains memory contents for uninitialized memory at {addr:08x}"
                     )
                     .into());
                 }
-                if Opts::global().verbose {
+                if verbosity >= Verbosity::Debug {
                     println!(
-                        "{} segment {:#08x}->{:#08x} ({:#08x}->{:#08x})",
+                        "{} segment {:#08x}->{:#08x} ({:#08x}->{:#08x}) in {}",
                         if uninitialized {
                             "Uninitialized"
                         } else {
@@ -202,7 +752,8 @@ pub trait AddressRangesExt<'a>: IntoIterator<Item = &'a AddressRange> + Clone {
                         addr,
                         addr + size,
                         vaddr,
-                        vaddr + size
+                        vaddr + size,
+                        range
                     );
                 }
                 return Ok(*range);
@@ -216,23 +767,85 @@ pub trait AddressRangesExt<'a>: IntoIterator<Item = &'a AddressRange> + Clone {
         .into())
     }
 
+    /// Describes every segment with contents that `check_elf32_ph_entries` would reject, along
+    /// with the nearest range that may have contents
+    fn rejected_segments(&self, entries: &[Elf32PhEntry]) -> Vec<String> {
+        let mut rejected = Vec::new();
+
+        for (i, entry) in entries.iter().enumerate() {
+            let mapped_size = min(entry.filez, entry.memsz);
+            if entry.typ != PT_LOAD || mapped_size == 0 {
+                continue;
+            }
+
+            let segment = AddressRange::new(
+                entry.paddr,
+                entry.paddr.saturating_add(mapped_size),
+                address_range::AddressRangeType::Contents,
+            );
+            let reason = match self
+                .clone()
+                .into_iter()
+                .find(|range| range.contains_span(segment.from, mapped_size))
+            {
+                Some(range) if range.typ == address_range::AddressRangeType::NoContents => {
+                    format!("has contents for uninitialized memory {range}")
+                }
+                Some(_) => continue,
+                None => match self
+                    .clone()
+                    .into_iter()
+                    .find(|range| range.overlaps(&segment))
+                {
+                    Some(range) => format!("is only partly inside {range}"),
+                    None => "is outside of every valid range".to_string(),
+                },
+            };
+
+            let nearest = self
+                .clone()
+                .into_iter()
+                .filter(|range| range.typ == address_range::AddressRangeType::Contents)
+                .min_by_key(|range| {
+                    // The gap on either side, zero when they overlap
+                    range.from.saturating_sub(segment.to) + segment.from.saturating_sub(range.to)
+                });
+
+            rejected.push(format!(
+                "Segment {i} {:#08x}->{:#08x} {reason}, nearest valid range is {}",
+                segment.from,
+                segment.to,
+                nearest.map_or("none".to_string(), |range| range.to_string())
+            ));
+        }
+
+        rejected
+    }
+
     fn check_elf32_ph_entries(
         &self,
         entries: &[Elf32PhEntry],
-    ) -> Result<BTreeMap<u32, Vec<PageFragment>>, Box<dyn Error>> {
-        let mut pages = BTreeMap::<u32, Vec<PageFragment>>::new();
+        page_size: u32,
+        verbosity: Verbosity,
+    ) -> Result<PageMap, Box<dyn Error>> {
+        let mut pages = PageMap::new();
 
         for entry in entries {
             if entry.typ == PT_LOAD && entry.memsz > 0 {
                 let mapped_size = min(entry.filez, entry.memsz);
 
                 if mapped_size > 0 {
-                    let ar =
-                        self.check_address_range(entry.paddr, entry.vaddr, mapped_size, false)?;
+                    let ar = self.check_address_range(
+                        entry.paddr,
+                        entry.vaddr,
+                        mapped_size,
+                        false,
+                        verbosity,
+                    )?;
 
                     // we don't download uninitialized, generally it is BSS and should be zero-ed by crt0.S, or it may be COPY areas which are undefined
                     if ar.typ != address_range::AddressRangeType::Contents {
-                        if Opts::global().verbose {
+                        if verbosity >= Verbosity::Debug {
                             println!("ignored");
                         }
                         continue;
@@ -241,15 +854,17 @@ pub trait AddressRangesExt<'a>: IntoIterator<Item = &'a AddressRange> + Clone {
                     let mut remaining = mapped_size;
                     let mut file_offset = entry.offset;
                     while remaining > 0 {
-                        let off = addr & (PAGE_SIZE - 1);
-                        let len = min(remaining, PAGE_SIZE - off);
+                        let off = addr % page_size;
+                        let len = min(remaining, page_size - off);
 
                         // list of fragments
                         let fragments = pages.entry(addr - off).or_default();
 
                         // note if filesz is zero, we want zero init which is handled because the
                         // statement above creates an empty page fragment list
-                        // check overlap with any existing fragments
+                        // check overlap with any existing fragments. Neither is empty, so it
+                        // can't both start at or past the end of the other and end before its
+                        // start, the two sides only differ when it overlaps
                         for fragment in fragments.iter() {
                             if (off < fragment.page_offset + fragment.bytes)
                                 != ((off + len) <= fragment.page_offset)
@@ -273,6 +888,7 @@ pub trait AddressRangesExt<'a>: IntoIterator<Item = &'a AddressRange> + Clone {
                             entry.vaddr + entry.filez,
                             entry.memsz - entry.filez,
                             true,
+                            verbosity,
                         )?;
                     }
                 }
@@ -284,3 +900,380 @@ pub trait AddressRangesExt<'a>: IntoIterator<Item = &'a AddressRange> + Clone {
 }
 
 impl<'a, T> AddressRangesExt<'a> for T where T: IntoIterator<Item = &'a AddressRange> + Clone {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        address_range::{
+            FLASH_END, FLASH_START, MAIN_RAM_END, MAIN_RAM_START, RP2040_ADDRESS_RANGES_FLASH,
+            RP2040_ADDRESS_RANGES_RAM,
+        },
+        testutil::ElfBuilder,
+        Verbosity,
+    };
+    use std::io;
+
+    #[test]
+    pub fn segment_past_end_of_file() {
+        let elf = &include_bytes!("../hello_usb.elf")[..];
+        let mut input = io::Cursor::new(elf);
+        let eh = Elf32Header::from_read(&mut input).unwrap();
+        let mut entries = eh.read_elf32_ph_entries(&mut input).unwrap();
+        let file_len = elf.len() as u64;
+
+        assert!(check_elf32_ph_file_bounds(&entries, file_len).is_ok());
+
+        let load = entries.iter_mut().find(|e| e.typ == PT_LOAD).unwrap();
+        load.filez = file_len as u32;
+        load.memsz = file_len as u32;
+        let err = check_elf32_ph_file_bounds(&entries, file_len).unwrap_err();
+        assert!(err.to_string().contains("extends past the end of the file"));
+    }
+
+    #[test]
+    pub fn unsupported_headers() {
+        let read = |elf: ElfBuilder| Elf32Header::from_read(&mut elf.build()).map(|_| ());
+
+        assert!(read(ElfBuilder::new(0)).is_ok());
+
+        assert!(read(ElfBuilder::new(0).elf64()).is_ok());
+        let err = read(ElfBuilder::new(0).header(|eh| eh.common.arch_class = 3)).unwrap_err();
+        assert!(err.to_string().contains("ELF class 3"), "{err}");
+
+        assert!(read(ElfBuilder::new(0).big_endian()).is_ok());
+        let err = read(ElfBuilder::new(0).header(|eh| eh.common.endianness = 3)).unwrap_err();
+        assert!(err.to_string().contains("endianness 3"), "{err}");
+
+        let err = read(ElfBuilder::new(0).header(|eh| eh.common.abi = 3)).unwrap_err();
+        assert!(err.to_string().contains("ABI 3"), "{err}");
+
+        let err = read(ElfBuilder::new(0).elf_type(ET_REL)).unwrap_err();
+        assert!(err.to_string().contains("relocatable object"), "{err}");
+    }
+
+    #[test]
+    pub fn misaligned_segments() {
+        let entries = |offset_delta: u32| {
+            let mut input = ElfBuilder::new(0x20000001)
+                .segment(0x20000000, &[0; 16])
+                .build();
+            let eh = Elf32Header::from_read(&mut input).unwrap();
+            let mut entries = eh.read_elf32_ph_entries(&mut input).unwrap();
+            entries[0].align = 0x1000;
+            entries[0].vaddr = 0x20000000 + entries[0].offset + offset_delta;
+            entries
+        };
+
+        assert!(elf32_ph_alignment_problems(&entries(0)).is_empty());
+        assert!(elf32_ph_alignment_problems(&entries(0x1000)).is_empty());
+
+        let problems = elf32_ph_alignment_problems(&entries(4));
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("alignment"), "{problems:?}");
+    }
+
+    #[test]
+    pub fn overlapping_fragments() {
+        let check = |segments: &[(u32, usize)]| {
+            let mut elf = ElfBuilder::new(0x10000001);
+            for &(addr, len) in segments {
+                elf = elf.segment(addr, &vec![0xaa; len]);
+            }
+            let mut input = elf.build();
+            let eh = Elf32Header::from_read(&mut input).unwrap();
+            let entries = eh.read_elf32_ph_entries(&mut input).unwrap();
+            RP2040_ADDRESS_RANGES_FLASH.check_elf32_ph_entries(&entries, 256, Verbosity::Quiet)
+        };
+        let overlaps = |segments: &[(u32, usize)]| {
+            let err = check(segments).unwrap_err();
+            assert!(err.to_string().contains("overlap"), "{segments:x?}: {err}");
+        };
+
+        // Adjacent, in either order
+        let pages = check(&[(0x10000000, 0x10), (0x10000010, 0x10)]).unwrap();
+        assert_eq!(pages[&0x10000000].len(), 2);
+        check(&[(0x10000010, 0x10), (0x10000000, 0x10)]).unwrap();
+        check(&[(0x100000f0, 0x10), (0x10000100, 0x10)]).unwrap();
+
+        // Nested
+        overlaps(&[(0x10000000, 0x40), (0x10000010, 0x10)]);
+        overlaps(&[(0x10000010, 0x10), (0x10000000, 0x40)]);
+        overlaps(&[(0x10000010, 0x10), (0x10000010, 0x10)]);
+
+        // Partially overlapping, down to a single byte
+        overlaps(&[(0x10000000, 0x20), (0x10000010, 0x20)]);
+        overlaps(&[(0x10000010, 0x20), (0x10000000, 0x20)]);
+        overlaps(&[(0x10000000, 0x11), (0x10000010, 0x10)]);
+        overlaps(&[(0x10000010, 0x10), (0x10000000, 0x11)]);
+
+        // Overlapping only in the second page of a segment spanning two
+        overlaps(&[(0x100000f0, 0x20), (0x1000010f, 0x10)]);
+    }
+
+    #[test]
+    pub fn realized_pages() {
+        let data: Vec<u8> = (1..=0x100).map(|v| v as u8).collect();
+        let mut input = ElfBuilder::new(0x10000001)
+            .segment(0x10000080, &data)
+            .build();
+        let eh = Elf32Header::from_read(&mut input).unwrap();
+        let entries = eh.read_elf32_ph_entries(&mut input).unwrap();
+        let pages = RP2040_ADDRESS_RANGES_FLASH
+            .check_elf32_ph_entries(&entries, 256, Verbosity::Quiet)
+            .unwrap();
+
+        let realized: Vec<(u32, Vec<u8>)> = RealizedPages::new(&mut input, pages.range(..), 256)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(realized.len(), 2);
+        assert_eq!(realized[0].0, 0x10000000);
+        assert_eq!(realized[0].1[..0x80], [0; 0x80]);
+        assert_eq!(realized[0].1[0x80..], data[..0x80]);
+        assert_eq!(realized[1].0, 0x10000100);
+        assert_eq!(realized[1].1[..0x80], data[0x80..]);
+        assert_eq!(realized[1].1[0x80..], [0; 0x80]);
+    }
+
+    #[test]
+    pub fn forward_reader_seeks() {
+        struct CountSeeks<R> {
+            inner: R,
+            seeks: usize,
+        }
+        impl<R: Read> Read for CountSeeks<R> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.inner.read(buf)
+            }
+        }
+        impl<R: Seek> Seek for CountSeeks<R> {
+            fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+                self.seeks += 1;
+                self.inner.seek(to)
+            }
+        }
+
+        let elf = &include_bytes!("../hello_usb.elf")[..];
+        let mut input = io::Cursor::new(elf);
+        let eh = Elf32Header::from_read(&mut input).unwrap();
+        let entries = eh.read_elf32_ph_entries(&mut input).unwrap();
+        let pages = RP2040_ADDRESS_RANGES_FLASH
+            .check_elf32_ph_entries(&entries, PAGE_SIZE, Verbosity::Quiet)
+            .unwrap();
+
+        let mut direct = CountSeeks {
+            inner: io::Cursor::new(elf),
+            seeks: 0,
+        };
+        let expected = RealizedPages::new(&mut direct, pages.range(..), PAGE_SIZE)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let mut forward = ForwardReader::new(CountSeeks {
+            inner: io::Cursor::new(elf),
+            seeks: 0,
+        });
+        let realized = RealizedPages::new(&mut forward, pages.range(..), PAGE_SIZE)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(realized, expected);
+        assert!(direct.seeks >= pages.len(), "{}", direct.seeks);
+        // Only to the first segment and over the alignment gap to the second in the file
+        assert_eq!(forward.inner.seeks, 2);
+
+        // Seeking back or past the end still goes to the reader
+        assert_eq!(forward.seek(SeekFrom::Start(0)).unwrap(), 0);
+        let past_end = elf.len() as u64 + 10;
+        assert_eq!(forward.seek(SeekFrom::Start(past_end)).unwrap(), past_end);
+        assert_eq!(forward.inner.seeks, 4);
+        assert_eq!(forward.read(&mut [0; 4]).unwrap(), 0);
+
+        // A short skip that runs into the end lands where a plain seek would
+        let mut short = ForwardReader::new(io::Cursor::new([0u8; 16]));
+        assert_eq!(short.seek(SeekFrom::Start(4)).unwrap(), 4);
+        assert_eq!(short.seek(SeekFrom::Current(20)).unwrap(), 24);
+        assert_eq!(short.inner.position(), 24);
+        assert_eq!(short.seek(SeekFrom::Current(-4)).unwrap(), 20);
+        assert_eq!(short.inner.position(), 20);
+    }
+
+    #[test]
+    pub fn all_rejected_segments() {
+        let mut input = ElfBuilder::new(0x10000001)
+            .segment(0x10000000, &[0; 16])
+            .segment(0x20000000, &[0; 16])
+            .segment(0x30000000, &[0; 16])
+            .segment(0x14fffff0, &[0; 32])
+            .build();
+        let eh = Elf32Header::from_read(&mut input).unwrap();
+        let entries = eh.read_elf32_ph_entries(&mut input).unwrap();
+
+        let rejected = RP2040_ADDRESS_RANGES_FLASH.rejected_segments(&entries);
+        assert_eq!(rejected.len(), 3, "{rejected:?}");
+        assert!(
+            rejected[0].starts_with("Segment 1 0x20000000"),
+            "{rejected:?}"
+        );
+        assert!(rejected[0].contains("uninitialized"), "{rejected:?}");
+        assert!(
+            rejected[1].starts_with("Segment 2 0x30000000"),
+            "{rejected:?}"
+        );
+        assert!(rejected[1].contains("outside"), "{rejected:?}");
+        assert!(
+            rejected[2].contains("only partly inside Contents 0x10000000->0x15000000"),
+            "{rejected:?}"
+        );
+    }
+
+    #[test]
+    pub fn filtered_segments() {
+        let mut input = ElfBuilder::new(0x10000001)
+            .segment(0x10000000, &[0; 0x300])
+            .build();
+        let eh = Elf32Header::from_read(&mut input).unwrap();
+        let entries = eh.read_elf32_ph_entries(&mut input).unwrap();
+        let offset = entries[0].offset;
+
+        let ranges =
+            |bounds: &[(u32, u32)]| bounds.iter().map(|&(start, end)| start..end).collect();
+        let spans = |include: &[(u32, u32)], exclude: &[(u32, u32)]| {
+            let (include, exclude): (Vec<_>, Vec<_>) = (ranges(include), ranges(exclude));
+            filter_elf32_ph_entries(&entries, &include, &exclude)
+                .iter()
+                .map(|entry| (entry.paddr, entry.offset - offset, entry.filez))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(spans(&[], &[]), [(0x10000000, 0, 0x300)]);
+        assert_eq!(
+            spans(&[(0x10000000, 0x10000200)], &[(0x10000080, 0x10000100)]),
+            [(0x10000000, 0, 0x80), (0x10000100, 0x100, 0x100)]
+        );
+        assert_eq!(
+            spans(&[(0x0fff0000, 0x10000100), (0x10000100, 0x10000180)], &[]),
+            [(0x10000000, 0, 0x180)]
+        );
+        assert_eq!(spans(&[(0x20000000, 0x20001000)], &[]), []);
+
+        let excluded = |exclude: &[(u32, u32)]| excluded_contents(&entries, &ranges(exclude));
+        assert_eq!(
+            excluded(&[(0x10000080, 0x10000100), (0x100002f0, 0x10001000)]),
+            [
+                (0x10000080..0x10000100, 0x80),
+                (0x100002f0..0x10001000, 0x10)
+            ]
+        );
+        assert_eq!(excluded(&[(0x10000300, 0x10001000)]), []);
+    }
+
+    #[test]
+    pub fn segment_past_address_space() {
+        let mut input = io::Cursor::new(&include_bytes!("../hello_usb.elf")[..]);
+        let eh = Elf32Header::from_read(&mut input).unwrap();
+        let mut entries = eh.read_elf32_ph_entries(&mut input).unwrap();
+
+        assert!(check_elf32_ph_address_bounds(&entries).is_ok());
+
+        let load = entries.iter_mut().find(|e| e.typ == PT_LOAD).unwrap();
+        load.paddr = 0xffffff00;
+        load.memsz = 0x200;
+        let err = check_elf32_ph_address_bounds(&entries).unwrap_err();
+        assert!(err.to_string().contains("only addresses 32 bits"), "{err}");
+
+        // Ending at 4 GiB its end is no 32 bit address, one byte below it is
+        let load = entries.iter_mut().find(|e| e.typ == PT_LOAD).unwrap();
+        load.memsz = 0x100;
+        assert!(check_elf32_ph_address_bounds(&entries).is_err());
+        let load = entries.iter_mut().find(|e| e.typ == PT_LOAD).unwrap();
+        load.memsz = 0xff;
+        load.vaddr = load.paddr;
+        assert!(check_elf32_ph_address_bounds(&entries).is_ok());
+    }
+
+    #[test]
+    pub fn crc_of_hello_usb_image() {
+        let mut input = io::Cursor::new(&include_bytes!("../hello_usb.elf")[..]);
+        let eh = Elf32Header::from_read(&mut input).unwrap();
+        let entries = eh.read_elf32_ph_entries(&mut input).unwrap();
+        let pages = RP2040_ADDRESS_RANGES_FLASH
+            .check_elf32_ph_entries(&entries, PAGE_SIZE, Verbosity::Quiet)
+            .unwrap();
+
+        // The image is contiguous, so the CRC must match the concatenated UF2 payloads
+        let end = 0x100058b0;
+        let image: Vec<u8> = include_bytes!("../hello_usb.uf2")
+            .chunks_exact(512)
+            .flat_map(|block| &block[32..32 + PAGE_SIZE as usize])
+            .copied()
+            .take((end - FLASH_START) as usize)
+            .collect();
+        let crc = |fill: &[u8]| {
+            let mut crc = Crc32::new();
+            crc.update(&image);
+            crc.update(fill);
+            crc.finish()
+        };
+
+        assert_eq!(
+            image_crc32(&mut input, &pages, PAGE_SIZE, end, 0).unwrap(),
+            crc(&[])
+        );
+
+        // Past the end of the image the fill byte is included
+        assert_eq!(
+            image_crc32(&mut input, &pages, PAGE_SIZE, end + 8, 0).unwrap(),
+            crc(&[0; 8])
+        );
+        assert_eq!(
+            image_crc32(&mut input, &pages, PAGE_SIZE, end + 8, 0xff).unwrap(),
+            crc(&[0xff; 8])
+        );
+    }
+
+    #[test]
+    pub fn range_boundaries_are_half_open() {
+        let flash = RP2040_ADDRESS_RANGES_FLASH;
+        assert!(flash.is_address_initialized(FLASH_START));
+        assert!(flash.is_address_initialized(FLASH_END - 1));
+        assert!(!flash.is_address_initialized(FLASH_END));
+
+        // A segment ending exactly at the end of a range is inside it
+        assert!(flash
+            .check_address_range(
+                FLASH_END - PAGE_SIZE,
+                FLASH_END - PAGE_SIZE,
+                PAGE_SIZE,
+                false,
+                Verbosity::Quiet,
+            )
+            .is_ok());
+        assert!(flash
+            .check_address_range(
+                FLASH_END - PAGE_SIZE,
+                FLASH_END - PAGE_SIZE,
+                PAGE_SIZE + 1,
+                false,
+                Verbosity::Quiet,
+            )
+            .is_err());
+
+        let ram = RP2040_ADDRESS_RANGES_RAM;
+        assert!(ram.is_address_initialized(MAIN_RAM_START));
+        assert!(ram.is_address_initialized(MAIN_RAM_END - 1));
+        assert!(!ram.is_address_initialized(MAIN_RAM_END));
+        assert!(ram
+            .check_address_range(
+                MAIN_RAM_END - 4,
+                MAIN_RAM_END - 4,
+                4,
+                false,
+                Verbosity::Quiet
+            )
+            .is_ok());
+        assert!(ram
+            .check_address_range(MAIN_RAM_END, MAIN_RAM_END, 4, false, Verbosity::Quiet)
+            .is_err());
+    }
+}
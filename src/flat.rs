@@ -0,0 +1,78 @@
+//! Flat binary output
+
+use std::{error::Error, io::Write};
+
+/// Writes `segments`, each an address and its contents, as one flat image from the lowest to
+/// the highest address. Gaps between segments are filled with `fill_byte`. Fails without writing
+/// anything when the image would be larger than `max_size` bytes
+pub fn write_flat<'a>(
+    segments: impl IntoIterator<Item = (u32, &'a [u8])>,
+    fill_byte: u8,
+    max_size: u64,
+    mut output: impl Write,
+) -> Result<(), Box<dyn Error>> {
+    let segments: Vec<_> = segments
+        .into_iter()
+        .filter(|(_, data)| !data.is_empty())
+        .map(|(addr, data)| (u64::from(addr), data))
+        .collect();
+
+    let Some(start) = segments.iter().map(|(addr, _)| *addr).min() else {
+        return Ok(());
+    };
+    let end = segments
+        .iter()
+        .map(|(addr, data)| addr + data.len() as u64)
+        .max()
+        .unwrap();
+
+    if end > 1 << 32 {
+        return Err(format!("Contents at {start:#x}..{end:#x} extend past 4 GiB").into());
+    }
+    if end - start > max_size {
+        return Err(format!(
+            "The flat binary of {start:#010x}..{end:#010x} would be {} bytes, more than the maximum of {max_size}",
+            end - start
+        )
+        .into());
+    }
+
+    let mut image = vec![fill_byte; (end - start) as usize];
+    for (addr, data) in segments {
+        let offset = (addr - start) as usize;
+        image[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    output.write_all(&image)?;
+    output.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn gaps_are_filled() {
+        let segments: [(u32, &[u8]); 3] = [
+            (0x1000_0010, &[4, 5]),
+            (0x1000_0000, &[1, 2, 3]),
+            (0x1000_0020, &[6]),
+        ];
+
+        let mut output = Vec::new();
+        write_flat(segments, 0xff, 0x21, &mut output).unwrap();
+
+        let mut expected = vec![0xff; 0x21];
+        expected[..3].copy_from_slice(&[1, 2, 3]);
+        expected[0x10..0x12].copy_from_slice(&[4, 5]);
+        expected[0x20] = 6;
+        assert_eq!(output, expected);
+
+        let mut output = Vec::new();
+        let err = write_flat(segments, 0xff, 0x20, &mut output).unwrap_err();
+        assert!(err.to_string().contains("would be 33 bytes"));
+        assert!(output.is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+//! Intel HEX output
+
+use std::{error::Error, io::Write};
+
+/// Bytes in a full data record, what objcopy writes
+const RECORD_LEN: usize = 16;
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// Writes `segments`, each an address and its contents, as Intel HEX with `entry` as start
+/// address. Records never cross a 64 KiB boundary, an extended linear address record comes
+/// before the first record of every other 64 KiB
+pub fn write_ihex<'a>(
+    segments: impl IntoIterator<Item = (u32, &'a [u8])>,
+    entry: u32,
+    mut output: impl Write,
+) -> Result<(), Box<dyn Error>> {
+    let mut upper = None;
+
+    for (addr, mut data) in segments {
+        let mut addr = u64::from(addr);
+
+        while !data.is_empty() {
+            if addr + data.len() as u64 > 1 << 32 {
+                return Err(format!("Contents at {addr:#x} extend past 4 GiB").into());
+            }
+
+            let addr_upper = (addr >> 16) as u16;
+            if upper != Some(addr_upper) {
+                write_record(
+                    &mut output,
+                    0,
+                    EXTENDED_LINEAR_ADDRESS,
+                    &addr_upper.to_be_bytes(),
+                )?;
+                upper = Some(addr_upper);
+            }
+
+            let to_boundary = 0x10000 - (addr & 0xffff) as usize;
+            let len = data.len().min(RECORD_LEN).min(to_boundary);
+            write_record(&mut output, addr as u16, DATA, &data[..len])?;
+
+            addr += len as u64;
+            data = &data[len..];
+        }
+    }
+
+    write_record(&mut output, 0, START_LINEAR_ADDRESS, &entry.to_be_bytes())?;
+    write_record(&mut output, 0, END_OF_FILE, &[])?;
+    output.flush()?;
+
+    Ok(())
+}
+
+fn write_record(
+    output: &mut impl Write,
+    offset: u16,
+    typ: u8,
+    data: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let mut record = vec![data.len() as u8];
+    record.extend_from_slice(&offset.to_be_bytes());
+    record.push(typ);
+    record.extend_from_slice(data);
+    let checksum = record
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+        .wrapping_neg();
+    record.push(checksum);
+
+    write!(output, ":")?;
+    for byte in record {
+        write!(output, "{byte:02X}")?;
+    }
+    writeln!(output)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// The bytes of every data record by address, and the start address
+    fn decode(hex: &str) -> (BTreeMap<u32, u8>, Option<u32>) {
+        let mut contents = BTreeMap::new();
+        let mut start = None;
+        let mut upper = 0;
+
+        for line in hex.lines() {
+            let bytes: Vec<u8> = (1..line.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&line[i..i + 2], 16).unwrap())
+                .collect();
+            assert_eq!(
+                bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)),
+                0,
+                "{line}"
+            );
+
+            let data = &bytes[4..bytes.len() - 1];
+            let offset = u32::from(u16::from_be_bytes([bytes[1], bytes[2]]));
+            match bytes[3] {
+                DATA => {
+                    for (i, byte) in data.iter().enumerate() {
+                        contents.insert(upper + offset + i as u32, *byte);
+                    }
+                }
+                EXTENDED_LINEAR_ADDRESS => {
+                    upper = u32::from(u16::from_be_bytes([data[0], data[1]])) << 16
+                }
+                START_LINEAR_ADDRESS => start = Some(u32::from_be_bytes(data.try_into().unwrap())),
+                END_OF_FILE => break,
+                typ => panic!("Unexpected record type {typ}"),
+            }
+        }
+
+        (contents, start)
+    }
+
+    #[test]
+    pub fn hello_usb_contents() {
+        let uf2 = include_bytes!("../hello_usb.uf2");
+        let segments = uf2.chunks_exact(512).map(|block| {
+            let addr = u32::from_le_bytes(block[12..16].try_into().unwrap());
+            (addr, &block[32..32 + 256])
+        });
+
+        let mut hex = Vec::new();
+        write_ihex(segments, 0x10000105, &mut hex).unwrap();
+        let hex = String::from_utf8(hex).unwrap();
+        assert!(hex.ends_with(":0400000510000105E1\n:00000001FF\n"));
+
+        // objcopy -O ihex only has the segment contents, the UF2 pages run to the end of the last
+        // page with zeroes
+        let (mut contents, start) = decode(&hex);
+        let (expected, expected_start) = decode(include_str!("../hello_usb.hex"));
+        assert_eq!(start, expected_start);
+        let (&last, _) = expected.last_key_value().unwrap();
+        let padding = contents.split_off(&(last + 1));
+        assert_eq!(contents, expected);
+        assert!(padding.values().all(|&byte| byte == 0));
+        assert_eq!(padding.keys().last(), Some(&0x100058ff));
+    }
+
+    #[test]
+    pub fn records_split_at_64k() {
+        let data = [0xaa; 32];
+        let mut hex = Vec::new();
+        write_ihex([(0x1000fff8, &data[..])], 0, &mut hex).unwrap();
+
+        let hex = String::from_utf8(hex).unwrap();
+        let lines: Vec<&str> = hex.lines().collect();
+        assert_eq!(lines[0], ":020000041000EA");
+        assert!(lines[1].starts_with(":08FFF800"));
+        assert_eq!(lines[2], ":020000041001E9");
+        assert!(lines[3].starts_with(":10000000"));
+        assert!(lines[4].starts_with(":08001000"));
+
+        let (contents, _) = decode(&hex);
+        assert_eq!(contents.len(), 32);
+        assert_eq!(contents.keys().next(), Some(&0x1000fff8));
+    }
+}
@@ -0,0 +1,2143 @@
+//! Converts ELF binaries to UF2, the format of the bootloaders of the RP2040 and other boards.
+//! The command line tool is built on `elf2uf2`
+
+use address_range::{AddressRange, AddressRangeType, MAIN_RAM_START};
+use assert_into::AssertInto;
+use boards::{AddressLocations, BoardInfo, ConversionParams};
+use boot2::{is_valid_boot2, BOOT2_SIZE};
+use elf::{
+    check_elf32_ph_address_bounds, check_elf32_ph_file_bounds, elf32_ph_alignment_problems,
+    excluded_contents, filter_elf32_ph_entries, image_crc32, realize_page, AddressRangesExt,
+    Elf32Header, EntryPoint, ForwardReader, PageMap, RealizedPages, PAGE_SIZE,
+};
+use picobin::{find_image_def, IMAGE_DEF_SEARCH_SIZE};
+use progress::{Eta, ProgressReporter};
+use static_assertions::const_assert;
+use std::{
+    collections::BTreeSet,
+    error::Error,
+    io::{self, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
+    mem,
+    ops::Range,
+};
+use uf2::{
+    Uf2Block, Uf2BlockData, Uf2Magics, Uf2Reader, UF2_BLOCK_SIZE, UF2_FLAG_FAMILY_ID_PRESENT,
+    UF2_MD5_TRAILER_SIZE,
+};
+
+pub mod address_range;
+pub mod boards;
+pub mod boot2;
+pub mod crc;
+pub mod elf;
+pub mod flat;
+pub mod ihex;
+pub mod md5;
+pub mod picobin;
+pub mod progress;
+#[cfg(test)]
+mod testutil;
+pub mod uf2;
+pub mod uf2toelf;
+#[cfg(feature = "usb")]
+pub mod usb;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    #[default]
+    Quiet,
+    /// What the conversion detected and decided
+    Info,
+    /// How each segment is mapped
+    Debug,
+    /// Every written block
+    Trace,
+}
+
+/// An input a conversion can read and seek in
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Opens another reader of the input of a conversion, for `elf2uf2_parallel` to give every
+/// worker its own
+pub type OpenInput<'a> = dyn Fn() -> io::Result<Box<dyn ReadSeek + 'a>> + Sync + 'a;
+
+/// Bytes of UF2 the pages are written as, one 512 byte block per page whatever the page size.
+/// Sizes a buffer or file for the output before converting. The blocks of
+/// `ConversionOptions::combine` come on top
+pub fn required_output_len(pages: &PageMap) -> usize {
+    pages.len() * UF2_BLOCK_SIZE
+}
+
+/// Describes the first page past the largest flash the board can have, if any
+fn flash_overflow(pages: &PageMap, params: &ConversionParams, board_name: &str) -> Option<String> {
+    let flash_start = params
+        .flash_ranges
+        .iter()
+        .find(|range| range.typ == AddressRangeType::Contents)?
+        .from;
+    let flash_end = flash_start.checked_add(params.flash_size?)?;
+
+    let (addr, _) = pages.range(flash_end..).next()?;
+    Some(format!(
+        "The page at {addr:#08x} is past the {} KiB of flash the {board_name} can have from {flash_start:#08x}",
+        params.flash_size? / 1024
+    ))
+}
+
+/// The data region of the block of every page, in page order, as the conversion loop would
+/// realize them one by one. Every worker reads the input through a reader it opens of its own
+#[cfg(feature = "parallel")]
+fn realize_blocks_parallel(
+    open: &OpenInput,
+    pages: &PageMap,
+    page_size: u32,
+    fill_byte: u8,
+    pad_byte: u8,
+) -> io::Result<Vec<Uf2BlockData>> {
+    use rayon::prelude::*;
+
+    let mut realized = vec![[0; mem::size_of::<Uf2BlockData>()]; pages.len()];
+
+    // Split no finer than a share of the pages per thread, each split opens a reader
+    let min_len = pages.len().div_ceil(rayon::current_num_threads());
+    let pages: Vec<_> = pages.values().collect();
+    realized
+        .par_iter_mut()
+        .zip(pages)
+        .with_min_len(min_len)
+        .try_for_each_init(
+            || open().map(ForwardReader::new),
+            |input, (data, fragments)| {
+                let input = input
+                    .as_mut()
+                    .map_err(|err| io::Error::new(err.kind(), err.to_string()))?;
+                clear_block_data(data, page_size, fill_byte, pad_byte);
+                realize_page(input, fragments, data, page_size)
+            },
+        )?;
+
+    Ok(realized)
+}
+
+/// Sets the payload of a block to `fill_byte` and the rest of its data region to `pad_byte`
+fn clear_block_data(data: &mut Uf2BlockData, page_size: u32, fill_byte: u8, pad_byte: u8) {
+    let (payload, rest) = data.split_at_mut(page_size.assert_into());
+    payload.fill(fill_byte);
+    rest.fill(pad_byte);
+}
+
+fn pad_flash_sectors(pages: &mut PageMap, page_size: u32, erase_size: u32) {
+    // Fill in empty dummy uf2 pages to align the binary to flash sectors (except for the last sector which we don't
+    // need to pad, and choose not to to avoid making all SDK UF2s bigger)
+    // That workaround is required because the bootrom uses the block number for erase sector calculations:
+    // https://github.com/raspberrypi/pico-bootrom/blob/c09c7f08550e8a36fc38dc74f8873b9576de99eb/bootrom/virtual_disk.c#L205
+
+    let touched_sectors: BTreeSet<u32> = pages.keys().map(|addr| addr / erase_size).collect();
+
+    let last_page_addr = *pages.last_key_value().unwrap().0;
+    for sector in touched_sectors {
+        let sector_start = sector * erase_size;
+        let mut page = sector_start - sector_start % page_size;
+
+        // Nothing past the last page is padded, so the page address never wraps at 4 GiB
+        while page < last_page_addr && page / erase_size <= sector {
+            pages.entry(page).or_default();
+            page += page_size;
+        }
+    }
+}
+
+/// Reads the blocks to put ahead of the converted image. They must not overlap any of its pages
+fn read_combined_blocks(
+    input: impl Read,
+    magics: Uf2Magics,
+    pages: &PageMap,
+    page_size: u32,
+) -> Result<Vec<Uf2Block>, Box<dyn Error>> {
+    let mut blocks = Vec::new();
+
+    for block in Uf2Reader::new(input) {
+        let block = block?;
+        if block.magics() != magics {
+            return Err(format!("Block {} to combine has bad magic", blocks.len()).into());
+        }
+
+        let start = block.header.target_addr;
+        let end = block
+            .header
+            .payload_size
+            .checked_add(start)
+            .filter(|_| block.header.payload_size <= mem::size_of::<Uf2BlockData>().assert_into())
+            .ok_or_else(|| format!("Block at {start:#08x} to combine has a bad payload size"))?;
+
+        if let Some(page) = pages.range(..end).next_back().map(|(page, _)| *page) {
+            if start.saturating_sub(page) < page_size {
+                return Err(format!(
+                    "Block at {start:#08x} to combine overlaps the image page at {page:#08x}"
+                )
+                .into());
+            }
+        }
+
+        blocks.push(block);
+    }
+
+    if blocks.is_empty() {
+        return Err("The UF2 to combine has no blocks".into());
+    }
+
+    Ok(blocks)
+}
+
+const CORTEX_M_VECTORS: [&str; 16] = [
+    "Initial SP",
+    "Reset",
+    "NMI",
+    "HardFault",
+    "MemManage",
+    "BusFault",
+    "UsageFault",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "SVCall",
+    "DebugMonitor",
+    "Reserved",
+    "PendSV",
+    "SysTick",
+];
+
+/// Prints the first 16 words of the page holding the entry point, labeled as a Cortex-M vector table
+fn dump_entry_page(
+    input: &mut (impl Read + Seek),
+    pages: &PageMap,
+    entry: u32,
+    page_size: u32,
+) -> Result<(), Box<dyn Error>> {
+    let page_addr = entry - entry % page_size;
+    println!("Entry point {entry:#08x} in page {page_addr:#08x}");
+
+    let mut realized = RealizedPages::new(input, pages.range(page_addr..=page_addr), page_size);
+    let Some(page) = realized.next() else {
+        println!("The entry point page has no contents");
+        return Ok(());
+    };
+    let (_, page) = page?;
+
+    for (i, (word, label)) in page.chunks_exact(4).zip(CORTEX_M_VECTORS).enumerate() {
+        let word = u32::from_le_bytes(word.try_into().unwrap());
+        println!("{:#08x}: {word:#010x} {label}", page_addr + 4 * i as u32);
+    }
+
+    Ok(())
+}
+
+/// Checks the first two words of the Cortex-M vector table at `addr`. The initial SP must be
+/// word aligned and within `main_ram`, if the board has one, and the reset handler a Thumb
+/// address in a page with contents. The reset handler need not be the ELF entry point, the pico
+/// SDK enters flash images through `_entry_point` ahead of it
+fn check_vector_table(
+    input: &mut (impl Read + Seek),
+    pages: &PageMap,
+    page_size: u32,
+    addr: u32,
+    main_ram: Option<Range<u32>>,
+) -> Result<(), Box<dyn Error>> {
+    // VTOR ignores the low 7 bits on every Cortex-M
+    if !addr.is_multiple_of(128) {
+        return Err(format!("Vector table address {addr:#08x} is not 128 byte aligned").into());
+    }
+
+    let page_addr = addr - addr % page_size;
+    let offset: usize = (addr - page_addr).assert_into();
+    let page = match RealizedPages::new(input, pages.range(page_addr..=page_addr), page_size).next()
+    {
+        Some(page) if !pages[&page_addr].is_empty() => page?.1,
+        _ => return Err(format!("The vector table at {addr:#08x} has no contents").into()),
+    };
+    let word = |i: usize| {
+        let start = offset + 4 * i;
+        u32::from_le_bytes(page[start..start + 4].try_into().unwrap())
+    };
+    let (initial_sp, reset) = (word(0), word(1));
+
+    // The stack grows down, so the initial SP may be the end of RAM
+    if !initial_sp.is_multiple_of(4)
+        || main_ram
+            .as_ref()
+            .is_some_and(|ram| !(ram.start < initial_sp && initial_sp <= ram.end))
+    {
+        return Err(format!(
+            "The vector table at {addr:#08x} has an initial SP of {initial_sp:#010x}, not a word in main RAM"
+        )
+        .into());
+    }
+
+    let reset_page = (reset & !1) - (reset & !1) % page_size;
+    if reset & 1 == 0 || pages.get(&reset_page).is_none_or(Vec::is_empty) {
+        return Err(format!(
+            "The vector table at {addr:#08x} has a reset handler of {reset:#010x}, not a Thumb address in the image"
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// A problem if the flash image in `pages` has no IMAGE_DEF block where the bootrom looks for it
+fn missing_image_def(
+    input: &mut (impl Read + Seek),
+    pages: &PageMap,
+    page_size: u32,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let Some(&start) = pages.keys().next() else {
+        return Ok(None);
+    };
+    let end = start.saturating_add(IMAGE_DEF_SEARCH_SIZE.assert_into());
+
+    let mut image = vec![0; IMAGE_DEF_SEARCH_SIZE];
+    for page in RealizedPages::new(input, pages.range(start..end), page_size) {
+        let (addr, data) = page?;
+        let offset: usize = (addr - start).assert_into();
+        let len = data.len().min(IMAGE_DEF_SEARCH_SIZE - offset);
+        image[offset..offset + len].copy_from_slice(&data[..len]);
+    }
+
+    Ok(find_image_def(&image).is_none().then(|| {
+        format!(
+            "The image at {start:#08x} has no IMAGE_DEF block in its first {IMAGE_DEF_SEARCH_SIZE} bytes, the bootrom will not boot it"
+        )
+    }))
+}
+
+/// Lets `ranges` have contents in the bootrom `rom` of the board, which they otherwise ignore
+fn include_rom(ranges: &mut [AddressRange], rom: Option<Range<u32>>) {
+    let Some(rom) = rom else {
+        return;
+    };
+    ranges
+        .iter_mut()
+        .filter(|range| {
+            range.typ == AddressRangeType::Ignore && range.from == rom.start && range.to == rom.end
+        })
+        .for_each(|range| range.typ = AddressRangeType::Contents);
+}
+
+/// What a conversion detected and wrote
+#[derive(Clone, Debug)]
+pub struct ConversionStats {
+    pub entry_point: EntryPoint,
+    /// From the first page of the ELF contents to the end of the last, combined blocks aside
+    pub address_range: Option<Range<u32>>,
+    /// Blocks written, including combined and padding blocks
+    pub blocks: usize,
+    /// Blocks without contents inserted to pad flash sectors
+    pub padding_blocks: usize,
+    /// Payload bytes of the padding blocks
+    pub padding_bytes: usize,
+}
+
+/// What a conversion does on top of converting for its board. The defaults convert without any
+/// of the options
+#[derive(Clone, Debug, Default)]
+pub struct ConversionOptions {
+    /// How much to print while converting
+    pub verbosity: Verbosity,
+    /// Turn warnings about the input or the target into errors
+    pub strict: bool,
+    /// UF2 family id to write instead of the board's
+    pub family_id: Option<u32>,
+    /// Fail instead of warn when the first flash page is not a valid boot2 stage
+    pub expect_boot2: bool,
+    /// Emit segments in the bootrom of the board instead of ignoring them
+    pub include_rom: bool,
+    /// Where to append a CRC32 of the flash image
+    pub append_crc32: Option<u32>,
+    /// Print the page of the entry point as a Cortex-M vector table
+    pub dump_entry: bool,
+    /// Entry point RAM binaries must have instead of the start of their first page in main RAM
+    pub ram_entry: Option<u32>,
+    /// Address of a Cortex-M vector table to check
+    pub vector_table: Option<u32>,
+    /// Remove blocks without any content
+    pub drop_empty_blocks: bool,
+    /// Only convert the parts of segments in these address ranges
+    pub include: Vec<Range<u32>>,
+    /// Leave out the parts of segments in these address ranges
+    pub exclude: Vec<Range<u32>>,
+    /// A UF2 whose blocks are put ahead of the converted image
+    pub combine: Option<Vec<u8>>,
+    /// Magic numbers of the blocks written and combined
+    pub magics: Uf2Magics,
+    /// Report every segment rejected for the board before converting
+    pub analyze: bool,
+    /// Store the MD5 of its payload in every block
+    pub md5: bool,
+    /// Byte written where the image has no contents
+    pub fill_byte: u8,
+    /// Byte filling the data region of every block past its payload
+    pub data_pad_byte: u8,
+    /// Number the blocks from this instead of 0
+    pub block_no_base: Option<u32>,
+    /// Block count of the whole file the blocks are part of
+    pub total_blocks: Option<u32>,
+}
+
+/// Converts the ELF `input` to UF2 blocks for `board`
+pub fn elf2uf2(
+    input: impl Read + Seek,
+    output: impl Write,
+    reporter: &mut dyn ProgressReporter,
+    board: &dyn BoardInfo,
+    options: &ConversionOptions,
+) -> Result<ConversionStats, Box<dyn Error>> {
+    elf2uf2_checked(input, output, reporter, board, options, &|_| Ok(()))
+}
+
+/// Converts an ELF already held in memory, for callers that have no seekable reader for it
+pub fn elf2uf2_from_bytes(
+    elf: &[u8],
+    output: impl Write,
+    reporter: &mut dyn ProgressReporter,
+    board: &dyn BoardInfo,
+    options: &ConversionOptions,
+) -> Result<ConversionStats, Box<dyn Error>> {
+    #[cfg(feature = "parallel")]
+    return elf2uf2_parallel(
+        Cursor::new(elf),
+        &|| Ok(Box::new(Cursor::new(elf))),
+        output,
+        reporter,
+        board,
+        options,
+        &|_| Ok(()),
+    );
+
+    #[cfg(not(feature = "parallel"))]
+    elf2uf2(Cursor::new(elf), output, reporter, board, options)
+}
+
+/// Like `elf2uf2_checked`, but the pages are read in parallel. `open` opens another reader of
+/// `input` for every worker, the output is the same byte for byte
+#[cfg(feature = "parallel")]
+pub fn elf2uf2_parallel(
+    input: impl Read + Seek,
+    open: &OpenInput,
+    output: impl Write,
+    reporter: &mut dyn ProgressReporter,
+    board: &dyn BoardInfo,
+    options: &ConversionOptions,
+    check_len: &dyn Fn(usize) -> Result<(), Box<dyn Error>>,
+) -> Result<ConversionStats, Box<dyn Error>> {
+    convert(
+        input,
+        Some(open),
+        output,
+        reporter,
+        board,
+        options,
+        check_len,
+    )
+}
+
+/// Like `elf2uf2`, `check_len` is given the length of the output before anything is written and
+/// can stop the conversion
+pub fn elf2uf2_checked(
+    input: impl Read + Seek,
+    output: impl Write,
+    reporter: &mut dyn ProgressReporter,
+    board: &dyn BoardInfo,
+    options: &ConversionOptions,
+    check_len: &dyn Fn(usize) -> Result<(), Box<dyn Error>>,
+) -> Result<ConversionStats, Box<dyn Error>> {
+    convert(input, None, output, reporter, board, options, check_len)
+}
+
+/// The conversion behind every `elf2uf2` function, reading the pages in parallel when given a
+/// way to `open` more readers of the input
+fn convert(
+    input: impl Read + Seek,
+    #[cfg_attr(not(feature = "parallel"), allow(unused_variables))] open: Option<&OpenInput>,
+    output: impl Write,
+    reporter: &mut dyn ProgressReporter,
+    board: &dyn BoardInfo,
+    options: &ConversionOptions,
+    check_len: &dyn Fn(usize) -> Result<(), Box<dyn Error>>,
+) -> Result<ConversionStats, Box<dyn Error>> {
+    let mut params = ConversionParams::from(board);
+    if let Some(family_id) = options.family_id {
+        params.family_id = family_id;
+    }
+    let locations = params.locations;
+
+    let page_size = params.page_size;
+    if page_size == 0 || page_size > mem::size_of::<Uf2BlockData>().assert_into() {
+        return Err(format!(
+            "Unsupported page size {page_size}, a UF2 block holds at most {} bytes",
+            mem::size_of::<Uf2BlockData>()
+        )
+        .into());
+    }
+    if options.md5
+        && page_size > (mem::size_of::<Uf2BlockData>() - UF2_MD5_TRAILER_SIZE).assert_into()
+    {
+        return Err(format!("A page size of {page_size} leaves no room for an MD5").into());
+    }
+
+    let mut input = ForwardReader::new(input);
+    let eh = Elf32Header::from_read(&mut input)?;
+
+    let entries = eh.read_elf32_ph_entries(&mut input)?;
+
+    check_elf32_ph_file_bounds(&entries, input.seek(SeekFrom::End(0))?)?;
+    check_elf32_ph_address_bounds(&entries)?;
+
+    for problem in elf32_ph_alignment_problems(&entries) {
+        if options.strict {
+            return Err(problem.into());
+        }
+        eprintln!("Warning: {problem}");
+    }
+
+    let entry_point = eh
+        .classify_entry(&entries, &params)
+        .ok_or("entry point is not in mapped part of file".to_string())?;
+    let ram_style = entry_point.ram_binary;
+
+    if options.verbosity >= Verbosity::Info {
+        println!(
+            "Detected {} binary, entry {:#08x} at {:#08x} in {}",
+            if ram_style { "RAM" } else { "FLASH" },
+            entry_point.entry,
+            entry_point.effective_entry,
+            entry_point.range
+        );
+    }
+
+    let mut valid_ranges = if ram_style {
+        params.ram_ranges
+    } else {
+        params.flash_ranges
+    }
+    .to_vec();
+
+    if options.include_rom {
+        include_rom(&mut valid_ranges, params.locations.rom()?);
+    }
+    let valid_ranges = valid_ranges.as_slice();
+
+    let paged_entries = filter_elf32_ph_entries(&entries, &options.include, &options.exclude);
+    for (range, bytes) in excluded_contents(&entries, &options.exclude) {
+        eprintln!(
+            "Warning: --exclude {:#010x}-{:#010x} leaves out {bytes} bytes of segment contents",
+            range.start, range.end
+        );
+    }
+    if options.analyze {
+        let rejected = valid_ranges.rejected_segments(&paged_entries);
+        println!("{} rejected segments", rejected.len());
+        for segment in rejected {
+            println!("  {segment}");
+        }
+    }
+
+    let mut pages =
+        valid_ranges.check_elf32_ph_entries(&paged_entries, page_size, options.verbosity)?;
+
+    if pages.is_empty() {
+        return Err("The input file has no memory pages".into());
+    }
+
+    if let Some(crc_addr) = options.append_crc32 {
+        if ram_style {
+            return Err("A CRC32 can only be appended to flash binaries".into());
+        }
+        if crc_addr % 4 != 0
+            || !valid_ranges.is_address_initialized(crc_addr)
+            || !valid_ranges.is_address_initialized(crc_addr + 3)
+        {
+            return Err(format!("CRC32 address {crc_addr:#08x} is not a word in flash").into());
+        }
+
+        let (last_page_addr, last_fragments) = pages.last_key_value().unwrap();
+        let image_end = last_fragments
+            .iter()
+            .map(|fragment| last_page_addr + fragment.page_offset + fragment.bytes)
+            .max()
+            .unwrap_or(*last_page_addr);
+        if crc_addr < image_end {
+            return Err(format!(
+                "CRC32 address {crc_addr:#08x} is inside the image, which ends at {image_end:#08x}"
+            )
+            .into());
+        }
+
+        pages.entry(crc_addr - crc_addr % page_size).or_default();
+    }
+
+    if ram_style {
+        let main_ram = locations.main_ram()?;
+        let xip_sram = locations.xip_sram()?;
+
+        // A board without RAM locations has nothing to check the entry point against. Without XIP
+        // SRAM bounds only the main RAM entry point is checked
+        if let Some(expected_ep) = options.ram_entry {
+            if eh.entry != expected_ep {
+                #[allow(clippy::unnecessary_cast)]
+                return Err(format!(
+                    "The RAM binary should have the --ram-entry entry point {:#08x} (not {:#08x})",
+                    expected_ep, eh.entry as u32
+                )
+                .into());
+            }
+        } else if main_ram.is_some() || xip_sram.is_some() {
+            let in_xip_sram = |addr: &u32| xip_sram.as_ref().is_some_and(|xip| xip.contains(addr));
+
+            let expected_ep_main_ram = pages
+                .keys()
+                .find(|addr| main_ram.as_ref().is_some_and(|ram| ram.contains(addr)))
+                .map(|addr| {
+                    if eh.has_thumb_entry() {
+                        addr | 0x1
+                    } else {
+                        *addr
+                    }
+                });
+
+            match expected_ep_main_ram {
+                Some(expected_ep) if eh.entry != expected_ep => {
+                    #[allow(clippy::unnecessary_cast)]
+                    return Err(format!(
+                        "A RAM binary should have an entry point at the beginning: {:#08x} (not {:#08x})",
+                        expected_ep, eh.entry as u32
+                    )
+                    .into());
+                }
+                Some(_) => (),
+                None if pages.keys().any(in_xip_sram) => {
+                    return Err("B0/B1 Boot ROM does not support direct entry into XIP_SRAM".into());
+                }
+                None if xip_sram.is_some() => {
+                    return Err("The RAM binary has no pages in main RAM or XIP SRAM".into());
+                }
+                None => return Err("The RAM binary has no pages in main RAM".into()),
+            }
+        }
+        const_assert!(0 == (MAIN_RAM_START & (PAGE_SIZE - 1)));
+    } else {
+        // The bootrom only enters images that start with a boot2 stage carrying a valid CRC, an image
+        // linked without it is accepted here but will never boot. Only checked when boot2 fits in the
+        // first page
+        if locations.flash_start.is_none() && options.expect_boot2 {
+            return Err(
+                "The board does not define where flash starts, boot2 can't be checked".into(),
+            );
+        }
+
+        if let Some(flash_start) = locations.flash_start.filter(|start| {
+            start.is_multiple_of(page_size) && page_size >= BOOT2_SIZE.assert_into()
+        }) {
+            let has_boot2 = match RealizedPages::new(
+                &mut input,
+                pages.range(flash_start..=flash_start),
+                page_size,
+            )
+            .next()
+            {
+                Some(page) => is_valid_boot2(&page?.1),
+                None => false,
+            };
+
+            if !has_boot2 {
+                if options.expect_boot2 {
+                    return Err(format!(
+                        "The page at {flash_start:#08x} is not a valid boot2 stage"
+                    )
+                    .into());
+                }
+                eprintln!(
+                    "Warning: the page at {flash_start:#08x} does not look like a boot2 stage, the bootrom may refuse to boot this image"
+                );
+            }
+        }
+
+        if board.requires_image_def() {
+            if let Some(problem) = missing_image_def(&mut input, &pages, page_size)? {
+                if options.strict {
+                    return Err(problem.into());
+                }
+                eprintln!("Warning: {problem}");
+            }
+        }
+
+        if let Some(problem) = flash_overflow(&pages, &params, board.name()) {
+            if options.strict {
+                return Err(problem.into());
+            }
+            eprintln!("Warning: {problem}");
+        }
+
+        pad_flash_sectors(&mut pages, page_size, params.erase_size);
+    }
+
+    // The CRC32 covers the image as it is written, padding included
+    let mut crc32_patch = None;
+    if let Some(crc_addr) = options.append_crc32 {
+        let crc = image_crc32(&mut input, &pages, page_size, crc_addr, options.fill_byte)?;
+        if options.verbosity >= Verbosity::Info {
+            println!("CRC32 {crc:#010x} at {crc_addr:#08x}");
+        }
+
+        let page_addr = crc_addr - crc_addr % page_size;
+        crc32_patch = Some((page_addr, crc_addr - page_addr, crc.to_le_bytes()));
+    }
+
+    // Where the vector table is depends on the board and the linker script, so it is only
+    // checked where asked to
+    if let Some(vector_table) = options.vector_table {
+        check_vector_table(
+            &mut input,
+            &pages,
+            page_size,
+            vector_table,
+            locations.main_ram()?,
+        )?;
+    }
+
+    if options.dump_entry {
+        dump_entry_page(&mut input, &pages, eh.entry, page_size)?;
+    }
+
+    if options.drop_empty_blocks {
+        pages.retain(|addr, fragments| {
+            !fragments.is_empty() || crc32_patch.is_some_and(|(page_addr, _, _)| page_addr == *addr)
+        });
+
+        if pages.is_empty() {
+            return Err("The input file has no memory pages with contents".into());
+        }
+    }
+
+    let padding_blocks = pages
+        .iter()
+        .filter(|(addr, fragments)| {
+            fragments.is_empty() && crc32_patch.is_none_or(|(page_addr, _, _)| page_addr != **addr)
+        })
+        .count();
+
+    let combined = match &options.combine {
+        Some(uf2) => read_combined_blocks(&uf2[..], options.magics, &pages, page_size)?,
+        None => Vec::new(),
+    };
+    let first_page_num = combined.len();
+    let num_blocks = first_page_num + pages.len();
+
+    // The blocks may be one part of a larger file, numbered from the base out of the total
+    let block_no_base: usize = options.block_no_base.unwrap_or(0).assert_into();
+    let total_blocks: usize = match options.total_blocks {
+        Some(total_blocks) => total_blocks.assert_into(),
+        None => block_no_base + num_blocks,
+    };
+    if block_no_base + num_blocks > total_blocks {
+        return Err(format!(
+            "{num_blocks} blocks numbered from {block_no_base} do not fit in a total of {total_blocks}"
+        )
+        .into());
+    }
+
+    let output_len = first_page_num * UF2_BLOCK_SIZE + required_output_len(&pages);
+    check_len(output_len)?;
+
+    let mut block = Uf2Block::with_magics(options.magics);
+    block.header.flags = UF2_FLAG_FAMILY_ID_PRESENT;
+    block.header.payload_size = page_size;
+    block.header.num_blocks = total_blocks.assert_into();
+    block.header.file_size = params.family_id;
+
+    // Blocks are written whole, buffer them so an unbuffered sink does not see a write per block
+    let mut output = BufWriter::new(output);
+
+    reporter.start(output_len);
+    let mut eta = Eta::new(output_len);
+
+    // The combined blocks come first and are renumbered as part of the output
+    for (block_no, mut combined_block) in combined.into_iter().enumerate() {
+        combined_block.header.block_no = (block_no_base + block_no).assert_into();
+        combined_block.header.num_blocks = total_blocks.assert_into();
+
+        output.write_all(&combined_block.to_bytes())?;
+        eta.advance(reporter, UF2_BLOCK_SIZE);
+    }
+
+    let address_range = pages
+        .first_key_value()
+        .zip(pages.last_key_value())
+        .map(|((&first, _), (&last, _))| first..last.saturating_add(page_size));
+    let last_page_num = num_blocks - 1;
+
+    #[cfg(feature = "parallel")]
+    let mut realized = match open {
+        Some(open) => Some(
+            realize_blocks_parallel(
+                open,
+                &pages,
+                page_size,
+                options.fill_byte,
+                options.data_pad_byte,
+            )?
+            .into_iter(),
+        ),
+        None => None,
+    };
+
+    for (page_num, (target_addr, fragments)) in (first_page_num..).zip(pages) {
+        block.header.target_addr = target_addr;
+        block.header.block_no = (block_no_base + page_num).assert_into();
+
+        #[allow(clippy::unnecessary_cast)]
+        if options.verbosity >= Verbosity::Trace {
+            println!(
+                "Page {} / {} {:#08x}",
+                block.header.block_no as u32,
+                block.header.num_blocks as u32,
+                block.header.target_addr as u32
+            );
+        }
+
+        #[cfg(feature = "parallel")]
+        let data = realized.as_mut().map(|realized| realized.next().unwrap());
+        #[cfg(not(feature = "parallel"))]
+        let data = None;
+
+        match data {
+            // Realized ahead of the loop from these same fragments
+            Some(data) => block.data = data,
+            None => {
+                clear_block_data(
+                    &mut block.data,
+                    page_size,
+                    options.fill_byte,
+                    options.data_pad_byte,
+                );
+
+                realize_page(&mut input, &fragments, &mut block.data, page_size)?;
+            }
+        }
+
+        if let Some((page_addr, offset, crc)) = crc32_patch {
+            if page_addr == target_addr {
+                let offset: usize = offset.assert_into();
+                block.data[offset..offset + crc.len()].copy_from_slice(&crc);
+            }
+        }
+
+        if options.md5 {
+            block.set_md5();
+        }
+
+        output.write_all(&block.to_bytes())?;
+
+        if page_num != last_page_num {
+            eta.advance(reporter, UF2_BLOCK_SIZE);
+        }
+    }
+
+    // Flush and drop the output before the progress bar is allowd to finish
+    output.flush()?;
+    drop(output);
+
+    eta.advance(reporter, UF2_BLOCK_SIZE);
+    reporter.finish();
+
+    Ok(ConversionStats {
+        entry_point,
+        address_range,
+        blocks: num_blocks,
+        padding_blocks,
+        padding_bytes: padding_blocks * page_size as usize,
+    })
+}
+
+/// Converts into a caller provided buffer instead of a growing one, returning the bytes written.
+/// Nothing is written unless the buffer has room for every block of the output
+pub fn elf2uf2_into(
+    input: impl Read + Seek,
+    buf: &mut [u8],
+    reporter: &mut dyn ProgressReporter,
+    board: &dyn BoardInfo,
+    options: &ConversionOptions,
+) -> Result<usize, Box<dyn Error>> {
+    let buf_len = buf.len();
+    let check_len = |len: usize| {
+        if len > buf_len {
+            return Err(format!(
+                "The output buffer of {buf_len} bytes is too small for {len} bytes of UF2"
+            )
+            .into());
+        }
+        Ok(())
+    };
+
+    let stats = elf2uf2_checked(
+        input,
+        Cursor::new(buf),
+        reporter,
+        board,
+        options,
+        &check_len,
+    )?;
+    Ok(stats.blocks * UF2_BLOCK_SIZE)
+}
+
+/// A board of nothing but the address ranges and family id given to `elf2uf2_with`. Its pages
+/// are its sectors and it has no locations, so nothing is padded and nothing board specific is
+/// checked
+struct RangesBoard<'a> {
+    family_id: u32,
+    ranges: &'a [AddressRange],
+}
+
+impl BoardInfo for RangesBoard<'_> {
+    fn name(&self) -> &str {
+        "custom"
+    }
+
+    fn family_id(&self) -> u32 {
+        self.family_id
+    }
+
+    fn flash_sector_erase_size(&self) -> u32 {
+        PAGE_SIZE
+    }
+
+    fn address_ranges_flash(&self) -> &[AddressRange] {
+        self.ranges
+    }
+
+    fn address_ranges_ram(&self) -> &[AddressRange] {
+        &[]
+    }
+
+    fn address_locations(&self) -> AddressLocations {
+        AddressLocations::default()
+    }
+}
+
+/// Converts for a chip there is no `BoardInfo` for. `ranges` gives the valid address ranges for
+/// the ELF header, `check_entry` accepts or refuses the entry point found in them. Nothing is
+/// padded and there are none of the boot checks of the boards
+pub fn elf2uf2_with<'a>(
+    mut input: impl Read + Seek,
+    output: impl Write,
+    reporter: &mut dyn ProgressReporter,
+    family_id: u32,
+    ranges: impl FnOnce(&Elf32Header) -> &'a [AddressRange],
+    check_entry: impl FnOnce(&EntryPoint) -> Result<(), Box<dyn Error>>,
+) -> Result<ConversionStats, Box<dyn Error>> {
+    let eh = Elf32Header::from_read(&mut input)?;
+    let entries = eh.read_elf32_ph_entries(&mut input)?;
+
+    let board = RangesBoard {
+        family_id,
+        ranges: ranges(&eh),
+    };
+    let entry_point = eh
+        .classify_entry(&entries, &ConversionParams::from(&board as &dyn BoardInfo))
+        .ok_or("entry point is not in mapped part of file")?;
+    check_entry(&entry_point)?;
+
+    input.seek(SeekFrom::Start(0))?;
+    elf2uf2(
+        input,
+        output,
+        reporter,
+        &board,
+        &ConversionOptions::default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address_range::{
+        AddressRange, FLASH_SECTOR_ERASE_SIZE, MAIN_RAM_END, RP2040_ADDRESS_RANGES_FLASH,
+        RP2040_ADDRESS_RANGES_RAM,
+    };
+    use crate::boards::{AddressLocations, Nrf52840, Teensy4, RP2040, RP2350};
+    use crate::elf::EM_ARM;
+    use crate::progress::NoProgress;
+    use crate::testutil::{ElfBuilder, TestBoard, EM_RISCV};
+    use crate::uf2::{RP2040_FAMILY_ID, RP2350_ARM_S_FAMILY_ID};
+    use std::{collections::BTreeMap, io};
+    use zerocopy::IntoBytes;
+
+    /// Collects the non zero payload bytes of a UF2 by address
+    fn uf2_contents(uf2: &[u8]) -> BTreeMap<u32, u8> {
+        let mut contents = BTreeMap::new();
+        for block in uf2.chunks_exact(512) {
+            let block = Uf2Block::from_bytes(block.try_into().unwrap()).unwrap();
+            for (i, byte) in block.data[..block.header.payload_size as usize]
+                .iter()
+                .enumerate()
+            {
+                if *byte != 0 {
+                    contents.insert(block.header.target_addr + i as u32, *byte);
+                }
+            }
+        }
+        contents
+    }
+
+    #[test]
+    pub fn hello_usb() {
+        let bytes_in = io::Cursor::new(&include_bytes!("../hello_usb.elf")[..]);
+        let mut bytes_out = Vec::new();
+        let stats = elf2uf2(
+            bytes_in,
+            &mut bytes_out,
+            &mut NoProgress,
+            &RP2040,
+            &ConversionOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(bytes_out, include_bytes!("../hello_usb.uf2"));
+
+        assert_eq!(stats.blocks, bytes_out.len() / UF2_BLOCK_SIZE);
+        assert_eq!(stats.address_range, Some(0x10000000..0x10005900));
+        assert_eq!(stats.padding_blocks, 0);
+        assert_eq!(stats.padding_bytes, 0);
+        assert!(!stats.entry_point.ram_binary);
+        assert_eq!(stats.entry_point.entry, 0x10000105);
+        assert_eq!(stats.entry_point.effective_entry, 0x10000105);
+        assert_eq!(stats.entry_point.range.from, 0x10000000);
+    }
+
+    #[test]
+    pub fn hello_serial() {
+        let bytes_in = io::Cursor::new(&include_bytes!("../hello_serial.elf")[..]);
+        let mut bytes_out = Vec::new();
+        elf2uf2(
+            bytes_in,
+            &mut bytes_out,
+            &mut NoProgress,
+            &RP2040,
+            &ConversionOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(bytes_out, include_bytes!("../hello_serial.uf2"));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    pub fn parallel_realization() {
+        let mut input = io::Cursor::new(&include_bytes!("../hello_usb.elf")[..]);
+        let eh = Elf32Header::from_read(&mut input).unwrap();
+        let entries = eh.read_elf32_ph_entries(&mut input).unwrap();
+        let pages = RP2040_ADDRESS_RANGES_FLASH
+            .check_elf32_ph_entries(&entries, PAGE_SIZE, Verbosity::Quiet)
+            .unwrap();
+
+        let elf = include_bytes!("../hello_usb.elf");
+        let parallel = realize_blocks_parallel(
+            &|| Ok(Box::new(io::Cursor::new(elf))),
+            &pages,
+            PAGE_SIZE,
+            0xff,
+            0xaa,
+        )
+        .unwrap();
+
+        assert_eq!(parallel.len(), pages.len());
+        for (fragments, parallel) in pages.values().zip(parallel) {
+            let mut serial = [0; 476];
+            clear_block_data(&mut serial, PAGE_SIZE, 0xff, 0xaa);
+            realize_page(&mut input, fragments, &mut serial, PAGE_SIZE).unwrap();
+            assert_eq!(parallel, serial);
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    pub fn parallel_conversion() {
+        let elf = &include_bytes!("../hello_usb.elf")[..];
+        let convert = |open: &OpenInput| {
+            let mut bytes_out = Vec::new();
+            elf2uf2_parallel(
+                io::Cursor::new(elf),
+                open,
+                &mut bytes_out,
+                &mut NoProgress,
+                &RP2040,
+                &ConversionOptions::default(),
+                &|_| Ok(()),
+            )
+            .map(|_| bytes_out)
+        };
+
+        let bytes_out = convert(&|| Ok(Box::new(io::Cursor::new(elf)))).unwrap();
+        assert_eq!(bytes_out, include_bytes!("../hello_usb.uf2"));
+
+        // A worker unable to open its reader fails the conversion with the I/O error
+        let err = convert(&|| Err(io::ErrorKind::NotFound.into()))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.downcast_ref::<io::Error>().map(io::Error::kind),
+            Some(io::ErrorKind::NotFound)
+        );
+        // As does one reading past the end of the input
+        let err = convert(&|| Ok(Box::new(io::Cursor::new(&elf[..0x1000]))))
+            .err()
+            .unwrap();
+        assert!(err.is::<io::Error>(), "{err}");
+    }
+
+    #[test]
+    pub fn convert_with_hooks() {
+        let elf = &include_bytes!("../hello_usb.elf")[..];
+        let convert = |ranges: &'static [AddressRange], entry: Option<u32>| {
+            let mut bytes_out = Vec::new();
+            elf2uf2_with(
+                io::Cursor::new(elf),
+                &mut bytes_out,
+                &mut NoProgress,
+                RP2040_FAMILY_ID,
+                |eh| {
+                    assert_eq!({ eh.common.machine }, EM_ARM);
+                    ranges
+                },
+                |entry_point| match entry {
+                    Some(entry) if entry_point.effective_entry != entry => {
+                        Err(format!("Entry {:#08x}", entry_point.effective_entry).into())
+                    }
+                    _ => Ok(()),
+                },
+            )
+            .map(|_| bytes_out)
+            .map_err(|err| err.to_string())
+        };
+
+        // hello_usb needs no sector padding on the RP2040 either, so the output is the same
+        let bytes_out = convert(RP2040_ADDRESS_RANGES_FLASH, Some(0x10000105)).unwrap();
+        assert_eq!(bytes_out, include_bytes!("../hello_usb.uf2"));
+
+        let err = convert(RP2040_ADDRESS_RANGES_FLASH, Some(0x10000101)).unwrap_err();
+        assert_eq!(err, "Entry 0x10000105");
+        let err = convert(RP2040_ADDRESS_RANGES_RAM, None).unwrap_err();
+        assert!(err.contains("not in mapped part"), "{err}");
+    }
+
+    #[test]
+    pub fn vector_table() {
+        let mut input = io::Cursor::new(&include_bytes!("../hello_usb.elf")[..]);
+        let eh = Elf32Header::from_read(&mut input).unwrap();
+        let entries = eh.read_elf32_ph_entries(&mut input).unwrap();
+        let pages = RP2040_ADDRESS_RANGES_FLASH
+            .check_elf32_ph_entries(&entries, PAGE_SIZE, Verbosity::Quiet)
+            .unwrap();
+        let mut check = |addr, main_ram| {
+            check_vector_table(&mut input, &pages, PAGE_SIZE, addr, main_ram)
+                .map_err(|err| err.to_string())
+        };
+        let main_ram = Some(MAIN_RAM_START..MAIN_RAM_END);
+
+        // The SDK puts the vector table after boot2 and the code entering the image
+        assert_eq!(check(0x10000200, main_ram.clone()), Ok(()));
+
+        let err = check(0x10000100, main_ram.clone()).unwrap_err();
+        assert!(err.contains("initial SP of 0xe0004827"), "{err}");
+        // Without main RAM only the alignment of the SP is checked
+        let err = check(0x10000000, None).unwrap_err();
+        assert!(err.contains("reset handler of 0x60582021"), "{err}");
+
+        let err = check(0x10000204, main_ram.clone()).unwrap_err();
+        assert!(err.contains("not 128 byte aligned"), "{err}");
+        let err = check(0x10100000, main_ram).unwrap_err();
+        assert!(err.contains("no contents"), "{err}");
+    }
+
+    #[test]
+    pub fn hello_usb_full_payload() {
+        let bytes_in = io::Cursor::new(&include_bytes!("../hello_usb.elf")[..]);
+        let mut bytes_out = Vec::new();
+        elf2uf2(
+            bytes_in,
+            &mut bytes_out,
+            &mut NoProgress,
+            &TestBoard {
+                page_size: 476,
+                ..Default::default()
+            },
+            &ConversionOptions::default(),
+        )
+        .unwrap();
+
+        for block in bytes_out.chunks_exact(512) {
+            let block = Uf2Block::from_bytes(block.try_into().unwrap()).unwrap();
+            assert_eq!({ block.header.payload_size }, 476);
+            assert_eq!({ block.header.target_addr } % 476, 0);
+        }
+
+        assert_eq!(
+            uf2_contents(&bytes_out),
+            uf2_contents(include_bytes!("../hello_usb.uf2"))
+        );
+
+        // 512 byte pages do not fit in the data region of a block
+        let bytes_in = io::Cursor::new(&include_bytes!("../hello_usb.elf")[..]);
+        let err = elf2uf2(
+            bytes_in,
+            &mut Vec::new(),
+            &mut NoProgress,
+            &TestBoard {
+                page_size: 512,
+                ..Default::default()
+            },
+            &ConversionOptions::default(),
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("at most 476 bytes"), "{err}");
+    }
+
+    #[test]
+    pub fn hello_usb_without_locations() {
+        let bytes_in = io::Cursor::new(&include_bytes!("../hello_usb.elf")[..]);
+        let mut bytes_out = Vec::new();
+        elf2uf2(
+            bytes_in,
+            &mut bytes_out,
+            &mut NoProgress,
+            &TestBoard {
+                ram_ranges: &[],
+                locations: AddressLocations::default(),
+                ..Default::default()
+            },
+            &ConversionOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(bytes_out, include_bytes!("../hello_usb.uf2"));
+    }
+
+    #[test]
+    pub fn ram_binary_entry_point() {
+        let convert = |machine, entry| {
+            elf2uf2(
+                ElfBuilder::new(entry)
+                    .machine(machine)
+                    .segment(0x20000000, &[0xaa; 256])
+                    .build(),
+                &mut Vec::new(),
+                &mut NoProgress,
+                &RP2040,
+                &ConversionOptions::default(),
+            )
+        };
+
+        assert!(convert(EM_ARM, 0x20000001).is_ok());
+        assert!(convert(EM_ARM, 0x20000000).is_err());
+        assert!(convert(EM_RISCV, 0x20000000).is_ok());
+        assert!(convert(EM_RISCV, 0x20000001).is_err());
+    }
+
+    #[test]
+    pub fn elf64_inputs() {
+        let convert = |elf: ElfBuilder| {
+            let mut bytes_out = Vec::new();
+            elf2uf2(
+                elf.build(),
+                &mut bytes_out,
+                &mut NoProgress,
+                &RP2350,
+                &ConversionOptions::default(),
+            )
+            .map(|_| bytes_out)
+        };
+        let riscv = || {
+            ElfBuilder::new(0x20000000)
+                .machine(EM_RISCV)
+                .segment(0x20000000, &[0xaa; 256])
+        };
+
+        assert_eq!(convert(riscv().elf64()).unwrap(), convert(riscv()).unwrap());
+        assert_eq!(
+            convert(riscv().elf64().big_endian()).unwrap(),
+            convert(riscv()).unwrap()
+        );
+
+        // Sets the high half of the 64 bit field at `offset`
+        let past_4gib = |offset: usize| {
+            let mut elf = riscv().elf64().build().into_inner();
+            elf[offset + 4] = 1;
+            elf2uf2(
+                io::Cursor::new(elf),
+                &mut Vec::new(),
+                &mut NoProgress,
+                &RP2350,
+                &ConversionOptions::default(),
+            )
+            .unwrap_err()
+        };
+
+        // The physical address of the only segment, past the ELF64 header
+        let err = past_4gib(64 + 24);
+        assert!(
+            err.to_string()
+                .contains("Segment 0 has physical address 0x120000000, past 4 GiB"),
+            "{err}"
+        );
+
+        let err = past_4gib(24);
+        assert!(
+            err.to_string()
+                .contains("entry point 0x120000000 is past 4 GiB"),
+            "{err}"
+        );
+    }
+
+    /// hello_usb with everything it puts in flash moved by `offset`
+    fn relocated_hello_usb(offset: u32) -> Vec<u8> {
+        let mut elf = include_bytes!("../hello_usb.elf").to_vec();
+        let mut input = io::Cursor::new(&elf[..]);
+        let mut eh = Elf32Header::from_read(&mut input).unwrap();
+        let mut entries = eh.read_elf32_ph_entries(&mut input).unwrap();
+        let ph_offset = input.position() as usize - mem::size_of_val(entries.as_slice());
+
+        eh.entry += offset;
+        for entry in &mut entries {
+            if entry.vaddr == entry.paddr {
+                entry.vaddr += offset;
+            }
+            entry.paddr += offset;
+        }
+        elf[..mem::size_of::<Elf32Header>()].copy_from_slice(eh.as_mut_bytes());
+        elf[ph_offset..ph_offset + mem::size_of_val(entries.as_slice())]
+            .copy_from_slice(entries.as_mut_slice().as_mut_bytes());
+
+        elf
+    }
+
+    #[test]
+    pub fn rp2350_flash_offsets() {
+        let convert = |offset| {
+            let mut bytes_out = Vec::new();
+            elf2uf2(
+                io::Cursor::new(relocated_hello_usb(offset)),
+                &mut bytes_out,
+                &mut NoProgress,
+                &RP2350,
+                &ConversionOptions::default(),
+            )
+            .map(|_| bytes_out)
+        };
+
+        // A partition 1 MiB into flash
+        let bytes_out = convert(0x100000).unwrap();
+        let expected: BTreeMap<u32, u8> = uf2_contents(include_bytes!("../hello_usb.uf2"))
+            .into_iter()
+            .map(|(addr, byte)| (addr + 0x100000, byte))
+            .collect();
+        assert_eq!(uf2_contents(&bytes_out), expected);
+
+        // Near the end of the 32 MiB window, and past it
+        assert!(convert(0x1ff0000).is_ok());
+        assert!(convert(0x2000000).is_err());
+    }
+
+    #[test]
+    pub fn include_rom_ranges() {
+        let with_rom = |board: &dyn BoardInfo, ranges: &[AddressRange]| {
+            let mut ranges = ranges.to_vec();
+            include_rom(&mut ranges, board.address_locations().rom().unwrap());
+            ranges.iter().map(|range| range.typ).collect::<Vec<_>>()
+        };
+        let types =
+            |ranges: &[AddressRange]| ranges.iter().map(|range| range.typ).collect::<Vec<_>>();
+
+        let ranges = with_rom(&RP2040, RP2040_ADDRESS_RANGES_RAM);
+        assert_eq!(ranges[2], AddressRangeType::Contents);
+        assert_eq!(ranges[..2], types(RP2040_ADDRESS_RANGES_RAM)[..2]);
+        assert_eq!(
+            with_rom(&RP2040, RP2040_ADDRESS_RANGES_FLASH),
+            types(RP2040_ADDRESS_RANGES_FLASH)
+        );
+        assert_eq!(
+            with_rom(&RP2350, RP2350.address_ranges_ram())[2],
+            AddressRangeType::Contents
+        );
+
+        // Ranges of other boards starting at 0 are not their bootrom
+        for board in [&Teensy4 as &dyn BoardInfo, &Nrf52840] {
+            let ranges = board.address_ranges_flash();
+            assert_eq!(ranges.iter().filter(|range| range.from == 0).count(), 1);
+            assert_eq!(with_rom(board, ranges), types(ranges));
+        }
+
+        // Nor is the ITCM of the Teensy 4 were it given the bootrom bounds
+        let mut ranges = Teensy4.address_ranges_flash().to_vec();
+        include_rom(&mut ranges, Some(0..0x80000));
+        assert_eq!(ranges[1].typ, AddressRangeType::NoContents);
+    }
+
+    #[test]
+    pub fn rp2350_xip_sram_entry() {
+        let convert = |addr, board: &dyn BoardInfo| {
+            elf2uf2(
+                ElfBuilder::new(addr | 1)
+                    .segment(addr, &[0xaa; 256])
+                    .build(),
+                &mut Vec::new(),
+                &mut NoProgress,
+                board,
+                &ConversionOptions::default(),
+            )
+        };
+
+        // The RP2350 XIP SRAM sits just below the end of its flash window
+        let err = convert(0x13ffc000, &RP2350).err().unwrap();
+        assert!(err.to_string().contains("XIP_SRAM"), "{err}");
+
+        // On the RP2040 the same address is flash
+        let stats = convert(0x13ffc000, &RP2040).unwrap();
+        assert!(!stats.entry_point.ram_binary);
+
+        // And the RP2040 XIP SRAM is nothing on the RP2350
+        let err = convert(0x15000000, &RP2040).err().unwrap();
+        assert!(err.to_string().contains("XIP_SRAM"), "{err}");
+        let err = convert(0x15000000, &RP2350).err().unwrap();
+        assert!(err.to_string().contains("not in mapped part"), "{err}");
+
+        let stats = convert(0x20000000, &RP2350).unwrap();
+        assert!(stats.entry_point.ram_binary);
+    }
+
+    #[test]
+    pub fn big_endian_elf() {
+        // Segment contents are copied as they are, only the headers depend on the endianness
+        let data: Vec<u8> = (0..0x100).map(|v| v as u8).collect();
+        let convert = |elf: ElfBuilder| {
+            let mut bytes_out = Vec::new();
+            elf2uf2(
+                elf.segment(0x20000000, &data).build(),
+                &mut bytes_out,
+                &mut NoProgress,
+                &RP2040,
+                &ConversionOptions::default(),
+            )
+            .unwrap();
+            bytes_out
+        };
+
+        let bytes_out = convert(ElfBuilder::new(0x20000001).big_endian());
+        assert_eq!(bytes_out, convert(ElfBuilder::new(0x20000001)));
+
+        let block = Uf2Block::from_bytes(bytes_out[..512].try_into().unwrap()).unwrap();
+        assert_eq!({ block.header.target_addr }, 0x20000000);
+        assert_eq!(block.data[..256], data[..]);
+    }
+
+    #[test]
+    pub fn ram_binary_blocks() {
+        let data: Vec<u8> = (0..0x180).map(|v| v as u8).collect();
+        let boards: [(&dyn BoardInfo, u32); 2] = [
+            (&RP2040, RP2040_FAMILY_ID),
+            (&RP2350, RP2350_ARM_S_FAMILY_ID),
+        ];
+
+        for (board, family_id) in boards {
+            let mut bytes_out = Vec::new();
+            elf2uf2(
+                ElfBuilder::new(0x20000001)
+                    .segment(0x20000000, &data)
+                    .build(),
+                &mut bytes_out,
+                &mut NoProgress,
+                board,
+                &ConversionOptions::default(),
+            )
+            .unwrap();
+
+            // RAM images are not padded to flash sectors
+            let blocks: Vec<Uf2Block> = Uf2Reader::new(&bytes_out[..])
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert_eq!(blocks.len(), 2);
+
+            for (i, block) in blocks.iter().enumerate() {
+                let header = block.header;
+                assert_eq!({ header.target_addr }, 0x20000000 + 256 * i as u32);
+                assert_eq!({ header.block_no }, i as u32);
+                assert_eq!({ header.num_blocks }, 2);
+                assert_eq!({ header.payload_size }, 256);
+                assert_eq!(block.family_id(), Some(family_id));
+            }
+            assert_eq!(blocks[0].data[..256], data[..256]);
+            assert_eq!(blocks[1].data[..0x80], data[256..]);
+            assert!(blocks[1].data[0x80..].iter().all(|&v| v == 0));
+        }
+    }
+
+    #[test]
+    pub fn padding_stats() {
+        // A segment half way into the first flash sector is padded from the start of the sector
+        let stats = elf2uf2(
+            ElfBuilder::new(0x10000801)
+                .segment(0x10000800, &[0xaa; 256])
+                .build(),
+            &mut Vec::new(),
+            &mut NoProgress,
+            &RP2040,
+            &ConversionOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(stats.blocks, 9);
+        assert_eq!(stats.padding_blocks, 8);
+        assert_eq!(stats.padding_bytes, 8 * 256);
+    }
+
+    #[test]
+    pub fn ram_binary_outside_main_ram() {
+        const BANKED_RAM: &[AddressRange] = &[AddressRange::new(
+            0x21000000,
+            0x21040000,
+            AddressRangeType::Contents,
+        )];
+
+        let err = elf2uf2(
+            ElfBuilder::new(0x21000001)
+                .segment(0x21000000, &[0xaa; 256])
+                .build(),
+            &mut Vec::new(),
+            &mut NoProgress,
+            &TestBoard {
+                ram_ranges: BANKED_RAM,
+                ..Default::default()
+            },
+            &ConversionOptions::default(),
+        )
+        .err()
+        .unwrap();
+        assert!(
+            err.to_string().contains("no pages in main RAM or XIP SRAM"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    pub fn teensy4_smoke() {
+        // Move hello_usb from RP2040 flash to Teensy flash
+        const OFFSET: u32 = 0x60000000 - 0x10000000;
+        let elf = relocated_hello_usb(OFFSET);
+
+        let mut bytes_out = Vec::new();
+        elf2uf2(
+            io::Cursor::new(elf),
+            &mut bytes_out,
+            &mut NoProgress,
+            &Teensy4,
+            &ConversionOptions::default(),
+        )
+        .unwrap();
+
+        for block in bytes_out.chunks_exact(512) {
+            let block = Uf2Block::from_bytes(block.try_into().unwrap()).unwrap();
+            assert_eq!(block.family_id(), Some(0x4fb2d5bd));
+        }
+
+        let expected: BTreeMap<u32, u8> = uf2_contents(include_bytes!("../hello_usb.uf2"))
+            .into_iter()
+            .map(|(addr, byte)| (addr + OFFSET, byte))
+            .collect();
+        assert_eq!(uf2_contents(&bytes_out), expected);
+    }
+
+    #[test]
+    pub fn nrf52840_smoke() {
+        let convert = |addr: u32| {
+            let mut bytes_out = Vec::new();
+            elf2uf2(
+                ElfBuilder::new(addr + 1)
+                    .segment(addr, &[0xaa; 0x200])
+                    .build(),
+                &mut bytes_out,
+                &mut NoProgress,
+                &Nrf52840,
+                &ConversionOptions::default(),
+            )
+            .map(|_| bytes_out)
+        };
+
+        // An application after the S140 SoftDevice
+        let bytes_out = convert(0x26000).unwrap();
+        for block in bytes_out.chunks_exact(512) {
+            let block = Uf2Block::from_bytes(block.try_into().unwrap()).unwrap();
+            assert_eq!(block.family_id(), Some(0xada52840));
+        }
+        assert_eq!(
+            uf2_contents(&bytes_out).keys().next().copied(),
+            Some(0x26000)
+        );
+
+        // Only flash images, the bootloader doesn't write RAM
+        assert!(convert(0x20000000).is_err());
+    }
+
+    #[test]
+    pub fn progress_closures() {
+        let mut total = 0;
+        let mut advanced = 0;
+        let mut finished = false;
+        let mut reporter = progress::FnProgress::new(
+            |bytes| total = bytes,
+            |bytes| advanced += bytes,
+            || finished = true,
+        );
+
+        let bytes_in = io::Cursor::new(&include_bytes!("../hello_usb.elf")[..]);
+        elf2uf2(
+            bytes_in,
+            &mut Vec::new(),
+            &mut reporter,
+            &RP2040,
+            &ConversionOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(total, include_bytes!("../hello_usb.uf2").len());
+        assert_eq!(advanced, total);
+        assert!(finished);
+    }
+
+    #[test]
+    pub fn convert_into_buffer() {
+        let expected = include_bytes!("../hello_usb.uf2");
+        let convert = |buf: &mut [u8]| {
+            let bytes_in = io::Cursor::new(&include_bytes!("../hello_usb.elf")[..]);
+            elf2uf2_into(
+                bytes_in,
+                buf,
+                &mut NoProgress,
+                &RP2040,
+                &ConversionOptions::default(),
+            )
+        };
+
+        let mut buf = vec![0; expected.len()];
+        assert_eq!(convert(&mut buf).unwrap(), expected.len());
+        assert_eq!(buf, expected);
+
+        // A larger buffer is only written as far as the output goes
+        let mut buf = vec![0xaa; expected.len() + 1];
+        assert_eq!(convert(&mut buf).unwrap(), expected.len());
+        assert_eq!(buf[..expected.len()], expected[..]);
+        assert_eq!(buf[expected.len()], 0xaa);
+
+        let mut buf = vec![0xaa; expected.len() - 1];
+        let err = convert(&mut buf).err().unwrap();
+        assert!(err.to_string().contains("too small"), "{err}");
+        assert!(buf.iter().all(|&v| v == 0xaa));
+    }
+
+    #[test]
+    pub fn convert_from_bytes() {
+        let mut bytes_out = Vec::new();
+        let stats = elf2uf2_from_bytes(
+            include_bytes!("../hello_usb.elf"),
+            &mut bytes_out,
+            &mut NoProgress,
+            &RP2040,
+            &ConversionOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(bytes_out, include_bytes!("../hello_usb.uf2"));
+        assert_eq!(stats.blocks * UF2_BLOCK_SIZE, bytes_out.len());
+
+        // A truncated ELF is an error, not a shorter UF2
+        assert!(elf2uf2_from_bytes(
+            &include_bytes!("../hello_usb.elf")[..0x1000],
+            &mut Vec::new(),
+            &mut NoProgress,
+            &RP2040,
+            &ConversionOptions::default(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    pub fn output_len_of_pages() {
+        let mut input = io::Cursor::new(&include_bytes!("../hello_usb.elf")[..]);
+        let eh = Elf32Header::from_read(&mut input).unwrap();
+        let entries = eh.read_elf32_ph_entries(&mut input).unwrap();
+
+        // Bigger pages are fewer blocks of the same size
+        for page_size in [PAGE_SIZE, 476] {
+            let mut pages = RP2040_ADDRESS_RANGES_FLASH
+                .check_elf32_ph_entries(&entries, page_size, Verbosity::Quiet)
+                .unwrap();
+            pad_flash_sectors(&mut pages, page_size, FLASH_SECTOR_ERASE_SIZE);
+
+            let board = TestBoard {
+                page_size,
+                ..Default::default()
+            };
+            let mut bytes_out = Vec::new();
+            elf2uf2_from_bytes(
+                include_bytes!("../hello_usb.elf"),
+                &mut bytes_out,
+                &mut NoProgress,
+                &board,
+                &ConversionOptions::default(),
+            )
+            .unwrap();
+            assert_eq!(required_output_len(&pages), bytes_out.len());
+            assert_eq!(required_output_len(&pages), pages.len() * 512);
+        }
+    }
+
+    #[test]
+    pub fn blocks_are_buffered() {
+        struct CountingWriter {
+            bytes: Vec<u8>,
+            writes: usize,
+        }
+
+        impl Write for CountingWriter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.writes += 1;
+                self.bytes.write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let bytes_in = io::Cursor::new(&include_bytes!("../hello_usb.elf")[..]);
+        let mut output = CountingWriter {
+            bytes: Vec::new(),
+            writes: 0,
+        };
+        elf2uf2(
+            bytes_in,
+            &mut output,
+            &mut NoProgress,
+            &RP2040,
+            &ConversionOptions::default(),
+        )
+        .unwrap();
+
+        let blocks = output.bytes.len() / UF2_BLOCK_SIZE;
+        assert_eq!(output.bytes, include_bytes!("../hello_usb.uf2"));
+        assert!(
+            output.writes * 8 <= blocks,
+            "{} writes for {blocks} blocks",
+            output.writes
+        );
+    }
+
+    #[test]
+    pub fn combined_blocks_must_not_overlap() {
+        let bootloader = &include_bytes!("../hello_usb.uf2")[..];
+
+        let mut pages = PageMap::new();
+        pages.insert(0x1001_0000, Vec::new());
+        assert_eq!(
+            read_combined_blocks(bootloader, Uf2Magics::default(), &pages, 256)
+                .unwrap()
+                .len(),
+            bootloader.len() / UF2_BLOCK_SIZE
+        );
+
+        pages.insert(0x1000_5700, Vec::new());
+        assert_eq!(required_output_len(&pages), 2 * UF2_BLOCK_SIZE);
+        let err = read_combined_blocks(bootloader, Uf2Magics::default(), &pages, 256)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("overlaps"), "{err}");
+    }
+
+    #[test]
+    pub fn flash_past_board_size() {
+        let params = ConversionParams::from(&RP2040 as &dyn BoardInfo);
+        let mut pages = PageMap::new();
+        pages.insert(0x10ffff00, Vec::new());
+        assert_eq!(flash_overflow(&pages, &params, "rp2040"), None);
+
+        pages.insert(0x11000000, Vec::new());
+        let problem = flash_overflow(&pages, &params, "rp2040").unwrap();
+        assert!(problem.contains("0x11000000"), "{problem}");
+
+        let params = ConversionParams::from(&RP2350 as &dyn BoardInfo);
+        assert_eq!(flash_overflow(&pages, &params, "rp2350"), None);
+    }
+
+    #[test]
+    pub fn data_region_padding() {
+        let mut data: Uf2BlockData = [0xaa; 476];
+        clear_block_data(&mut data, 256, 0, 0xff);
+        assert!(data[..256].iter().all(|&v| v == 0));
+        assert!(data[256..].iter().all(|&v| v == 0xff));
+
+        // A padding page has no fragments and is left with the fill byte
+        clear_block_data(&mut data, 256, 0xff, 0);
+        let mut input = io::Cursor::new(Vec::new());
+        realize_page(&mut input, &[], &mut data, 256).unwrap();
+        assert!(data[..256].iter().all(|&v| v == 0xff));
+        assert!(data[256..].iter().all(|&v| v == 0));
+    }
+
+    #[test]
+    pub fn sector_padding_uses_erase_size() {
+        let padded = |erase_size: u32| {
+            let mut pages = PageMap::new();
+            pages.insert(0x1000_0000, Vec::new());
+            pages.insert(0x1000_4000, Vec::new());
+            pad_flash_sectors(&mut pages, 256, erase_size);
+            pages.into_keys().collect::<Vec<_>>()
+        };
+
+        let sector = |start: u32, size: u32| (start..start + size).step_by(256);
+
+        assert_eq!(
+            padded(4096),
+            sector(0x1000_0000, 4096)
+                .chain([0x1000_4000])
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            padded(8192),
+            sector(0x1000_0000, 8192)
+                .chain([0x1000_4000])
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    pub fn converts_up_to_4_gib() {
+        static TOP_FLASH: [AddressRange; 1] = [AddressRange::new(
+            0xffff_0000,
+            u32::MAX,
+            AddressRangeType::Contents,
+        )];
+        let board = TestBoard {
+            flash_ranges: &TOP_FLASH,
+            locations: AddressLocations::default(),
+            ..Default::default()
+        };
+        let convert = |len| {
+            let elf = ElfBuilder::new(0xffff_ff01).segment(0xffff_ff00, &vec![0xaa; len]);
+            let mut bytes_out = Vec::new();
+            elf2uf2(
+                elf.build(),
+                &mut bytes_out,
+                &mut NoProgress,
+                &board,
+                &ConversionOptions::default(),
+            )
+            .map(|_| bytes_out)
+            .map_err(|err| err.to_string())
+        };
+
+        // The sector is padded up to the last page, which is the one to end below 4 GiB
+        let bytes_out = convert(0xff).unwrap();
+        assert_eq!(bytes_out.len(), 0x10 * UF2_BLOCK_SIZE);
+        assert_eq!(uf2_contents(&bytes_out).len(), 0xff);
+
+        // Ending at 0xffff_ffff + 1 the end is no longer a 32 bit address
+        let err = convert(0x100).unwrap_err();
+        assert!(err.contains("4 GiB"), "{err}");
+    }
+
+    /// The blocks of `elf` converted for the RP2040 with `options`
+    fn convert_with(
+        elf: ElfBuilder,
+        options: &ConversionOptions,
+    ) -> Result<Vec<Uf2Block>, Box<dyn Error>> {
+        let mut bytes_out = Vec::new();
+        elf2uf2(
+            elf.build(),
+            &mut bytes_out,
+            &mut NoProgress,
+            &RP2040,
+            options,
+        )?;
+        Ok(Uf2Reader::new(&bytes_out[..]).collect::<io::Result<_>>()?)
+    }
+
+    /// A RAM binary of two pages
+    fn ram_elf() -> ElfBuilder {
+        ElfBuilder::new(0x20000001).segment(0x20000000, &[0xaa; 0x200])
+    }
+
+    /// A flash binary of one page half way into the first flash sector
+    fn flash_elf() -> ElfBuilder {
+        ElfBuilder::new(0x10000801).segment(0x10000800, &[0xaa; 0x100])
+    }
+
+    #[test]
+    pub fn converts_with_md5() {
+        let options = ConversionOptions {
+            md5: true,
+            ..Default::default()
+        };
+        let blocks = convert_with(ram_elf(), &options).unwrap();
+
+        assert_eq!(blocks.len(), 2);
+        for block in &blocks {
+            let (addr, len, digest) = block.md5().unwrap();
+            assert_eq!(addr, { block.header.target_addr });
+            assert_eq!(len, 256);
+            assert_eq!(digest, crate::md5::md5(&block.data[..256]));
+        }
+
+        assert!(convert_with(ram_elf(), &Default::default()).unwrap()[0]
+            .md5()
+            .is_none());
+    }
+
+    #[test]
+    pub fn converts_with_fill_byte() {
+        let padding = |options: &ConversionOptions| {
+            let blocks = convert_with(flash_elf(), options).unwrap();
+            assert_eq!(blocks.len(), 9);
+            blocks
+                .iter()
+                .filter(|block| block.header.target_addr != 0x10000800)
+                .flat_map(|block| block.data[..256].to_vec())
+                .collect::<Vec<u8>>()
+        };
+
+        assert!(padding(&Default::default()).iter().all(|&v| v == 0));
+        let options = ConversionOptions {
+            fill_byte: 0xff,
+            ..Default::default()
+        };
+        assert!(padding(&options).iter().all(|&v| v == 0xff));
+    }
+
+    #[test]
+    pub fn converts_with_block_numbering() {
+        let options = ConversionOptions {
+            block_no_base: Some(3),
+            total_blocks: Some(10),
+            ..Default::default()
+        };
+        let blocks = convert_with(ram_elf(), &options).unwrap();
+        for (i, block) in blocks.iter().enumerate() {
+            assert_eq!({ block.header.block_no }, 3 + i as u32);
+            assert_eq!({ block.header.num_blocks }, 10);
+        }
+
+        // Without a total the blocks end the file
+        let options = ConversionOptions {
+            block_no_base: Some(3),
+            ..Default::default()
+        };
+        let blocks = convert_with(ram_elf(), &options).unwrap();
+        assert_eq!({ blocks[1].header.num_blocks }, 5);
+
+        let options = ConversionOptions {
+            block_no_base: Some(3),
+            total_blocks: Some(4),
+            ..Default::default()
+        };
+        let err = convert_with(ram_elf(), &options).err().unwrap();
+        assert!(err.to_string().contains("do not fit"), "{err}");
+    }
+
+    #[test]
+    pub fn converts_with_include_exclude() {
+        let convert = |include: Option<Range<u32>>, exclude: Option<Range<u32>>| {
+            let options = ConversionOptions {
+                include: include.into_iter().collect(),
+                exclude: exclude.into_iter().collect(),
+                ..Default::default()
+            };
+            convert_with(ram_elf(), &options).unwrap()
+        };
+        let addrs = |blocks: &[Uf2Block]| {
+            blocks
+                .iter()
+                .map(|block| block.header.target_addr)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(addrs(&convert(None, None)), [0x20000000, 0x20000100]);
+        assert_eq!(
+            addrs(&convert(Some(0x20000000..0x20000100), None)),
+            [0x20000000]
+        );
+        assert_eq!(
+            addrs(&convert(None, Some(0x20000100..0x20000200))),
+            [0x20000000]
+        );
+
+        // Within a page the left out bytes read as the fill byte
+        let blocks = convert(Some(0x20000000..0x20000180), Some(0x20000080..0x20000100));
+        assert_eq!(addrs(&blocks), [0x20000000, 0x20000100]);
+        assert!(blocks[0].data[..0x80].iter().all(|&v| v == 0xaa));
+        assert!(blocks[0].data[0x80..0x100].iter().all(|&v| v == 0));
+        assert!(blocks[1].data[..0x80].iter().all(|&v| v == 0xaa));
+        assert!(blocks[1].data[0x80..0x100].iter().all(|&v| v == 0));
+    }
+
+    #[test]
+    pub fn converts_with_drop_empty_blocks() {
+        let options = ConversionOptions {
+            drop_empty_blocks: true,
+            ..Default::default()
+        };
+        let blocks = convert_with(flash_elf(), &options).unwrap();
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!({ blocks[0].header.target_addr }, 0x10000800);
+        assert_eq!({ blocks[0].header.num_blocks }, 1);
+    }
+
+    #[test]
+    pub fn converts_with_append_crc32() {
+        let options = ConversionOptions {
+            append_crc32: Some(0x10000900),
+            drop_empty_blocks: true,
+            ..Default::default()
+        };
+        let blocks = convert_with(flash_elf(), &options).unwrap();
+
+        // From the start of the sector, which is padded
+        let mut crc = crate::crc::Crc32::new();
+        crc.update(&[0; 0x800]);
+        crc.update(&[0xaa; 0x100]);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!({ blocks[1].header.target_addr }, 0x10000900);
+        assert_eq!(blocks[1].data[..4], crc.finish().to_le_bytes());
+
+        // Not in flash, and not for RAM binaries
+        let options = ConversionOptions {
+            append_crc32: Some(0x20000000),
+            ..Default::default()
+        };
+        assert!(convert_with(flash_elf(), &options).is_err());
+        let options = ConversionOptions {
+            append_crc32: Some(0x10000900),
+            ..Default::default()
+        };
+        assert!(convert_with(ram_elf(), &options).is_err());
+    }
+
+    #[test]
+    pub fn converts_with_append_crc32_over_gaps() {
+        // Two sectors apart, the sector between them has no pages
+        let elf = ElfBuilder::new(0x10000001)
+            .segment(0x10000000, &[0xaa; 0x100])
+            .segment(0x10002000, &[0x55; 0x100]);
+        let options = ConversionOptions {
+            append_crc32: Some(0x10002100),
+            fill_byte: 0xff,
+            ..Default::default()
+        };
+        let blocks = convert_with(elf, &options).unwrap();
+        assert!(!blocks
+            .iter()
+            .any(|block| (0x10001000..0x10002000).contains(&{ block.header.target_addr })));
+
+        // The image as the blocks write it, with the fill byte where they write nothing
+        let mut image = vec![0xff; 0x2100];
+        for block in &blocks {
+            let offset = (block.header.target_addr - 0x10000000) as usize;
+            if offset < image.len() {
+                image[offset..offset + 256].copy_from_slice(&block.data[..256]);
+            }
+        }
+        assert!(image[0x100..0x2000].iter().all(|&v| v == 0xff));
+        let mut crc = crate::crc::Crc32::new();
+        crc.update(&image);
+
+        let crc_block = blocks
+            .iter()
+            .find(|block| block.header.target_addr == 0x10002100)
+            .unwrap();
+        assert_eq!(crc_block.data[..4], crc.finish().to_le_bytes());
+        assert!(crc_block.data[4..256].iter().all(|&v| v == 0xff));
+    }
+
+    #[test]
+    pub fn converts_with_combine() {
+        let mut block = Uf2Block::with_magics(Uf2Magics::default());
+        block.header.payload_size = 256;
+        block.header.target_addr = 0x10100000;
+        block.data[..256].fill(0x55);
+        let options = ConversionOptions {
+            combine: Some(block.as_bytes().to_vec()),
+            ..Default::default()
+        };
+        let blocks = convert_with(ram_elf(), &options).unwrap();
+
+        assert_eq!(blocks.len(), 3);
+        assert_eq!({ blocks[0].header.target_addr }, 0x10100000);
+        assert_eq!(blocks[0].data[..256], [0x55; 256]);
+        for (i, block) in blocks.iter().enumerate() {
+            assert_eq!({ block.header.block_no }, i as u32);
+            assert_eq!({ block.header.num_blocks }, 3);
+        }
+
+        block.header.target_addr = 0x20000080;
+        let options = ConversionOptions {
+            combine: Some(block.as_bytes().to_vec()),
+            ..Default::default()
+        };
+        let err = convert_with(ram_elf(), &options).err().unwrap();
+        assert!(err.to_string().contains("overlaps"), "{err}");
+    }
+
+    #[test]
+    pub fn converts_with_family_id() {
+        let options = ConversionOptions {
+            family_id: Some(0x12345678),
+            ..Default::default()
+        };
+        for block in convert_with(ram_elf(), &options).unwrap() {
+            assert_eq!(block.family_id(), Some(0x12345678));
+        }
+    }
+
+    #[test]
+    pub fn converts_with_strict() {
+        // Past the 16 MiB of flash the RP2040 can have, but still in its flash window
+        let elf = || ElfBuilder::new(0x11000001).segment(0x11000000, &[0xaa; 0x100]);
+        assert!(convert_with(elf(), &Default::default()).is_ok());
+
+        let options = ConversionOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let err = convert_with(elf(), &options).err().unwrap();
+        assert!(err.to_string().contains("16384 KiB of flash"), "{err}");
+    }
+
+    #[test]
+    pub fn converts_with_ram_entry() {
+        let elf = || ElfBuilder::new(0x20000101).segment(0x20000000, &[0xaa; 0x200]);
+        let err = convert_with(elf(), &Default::default()).err().unwrap();
+        assert!(err.to_string().contains("at the beginning"), "{err}");
+
+        let options = ConversionOptions {
+            ram_entry: Some(0x20000101),
+            ..Default::default()
+        };
+        assert!(convert_with(elf(), &options).is_ok());
+
+        let options = ConversionOptions {
+            ram_entry: Some(0x20000001),
+            ..Default::default()
+        };
+        let err = convert_with(elf(), &options).err().unwrap();
+        assert!(err.to_string().contains("--ram-entry"), "{err}");
+    }
+}
@@ -1,37 +1,49 @@
-use crate::address_range::{MAIN_RAM_END, XIP_SRAM_END, XIP_SRAM_START};
-use address_range::{
-    FLASH_SECTOR_ERASE_SIZE, MAIN_RAM_START, RP2040_ADDRESS_RANGES_FLASH, RP2040_ADDRESS_RANGES_RAM,
-};
+use address_range::{AddressRange, AddressRangeType};
 use assert_into::AssertInto;
-use clap::Parser;
-use elf::{realize_page, AddressRangesExt, Elf32Header, PAGE_SIZE};
-use pbr::{ProgressBar, Units};
-use static_assertions::const_assert;
+use boards::{BoardInfo, BoardIter, BoardRegistry, ConversionParams, RP2040};
+use clap::{Parser, Subcommand, ValueEnum};
+use elf::{AddressRangesExt, Elf32Header, PT_LOAD};
+#[cfg(feature = "parallel")]
+use elf2uf2_rs::elf2uf2_parallel;
+#[cfg(feature = "usb")]
+use elf2uf2_rs::usb;
+use elf2uf2_rs::{
+    address_range, boards, elf, elf2uf2, elf2uf2_checked, flat, ihex, progress, uf2, uf2toelf,
+    ConversionOptions, ConversionStats, ReadSeek, Verbosity,
+};
+use progress::{NoProgress, ProgressBarReporter, ProgressReporter};
 use std::{
-    collections::HashSet,
+    collections::BTreeMap,
     error::Error,
     fs::{self, File},
-    io::{BufReader, BufWriter, Read, Seek, Write},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    ops::Range,
     path::{Path, PathBuf},
+    process::ExitCode,
     sync::OnceLock,
 };
 use sysinfo::Disks;
 use uf2::{
-    Uf2BlockData, Uf2BlockFooter, Uf2BlockHeader, RP2040_FAMILY_ID, UF2_FLAG_FAMILY_ID_PRESENT,
-    UF2_MAGIC_END, UF2_MAGIC_START0, UF2_MAGIC_START1,
+    merge_uf2, split_uf2_by_family, validate_uf2, Uf2Block, Uf2Magics, Uf2Reader,
+    Uf2SeekableWriter, UF2_BLOCK_SIZE,
 };
-use zerocopy::IntoBytes;
 
-mod address_range;
-mod elf;
-mod uf2;
+#[cfg(feature = "serial")]
+mod serial;
 
 #[derive(Parser, Debug, Default)]
-#[clap(author = "Jonathan Nilsson")]
+#[clap(
+    author = "Jonathan Nilsson",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Opts {
-    /// Verbose
-    #[clap(short, long)]
-    verbose: bool,
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Verbose, repeat for more detail (-v, -vv, -vvv)
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
 
     /// Deploy to any connected pico
     #[clap(short, long)]
@@ -47,20 +59,403 @@ struct Opts {
     #[clap(short, long)]
     term: bool,
 
-    /// Input file
-    input: String,
+    /// Reboot the running pico on serial port PORT into BOOTSEL before deploying
+    #[cfg(feature = "serial")]
+    #[clap(long, value_name = "PORT", requires = "deploy")]
+    reboot: Option<String>,
+
+    /// Reboot a board that stays in bootloader mode after deploy (requires the usb feature)
+    #[clap(long, requires = "deploy")]
+    reset_after: bool,
+
+    /// Read the UF2 back from the pico after deploy and compare its blocks with those written
+    #[clap(long, requires = "deploy")]
+    verify: bool,
+
+    /// Attempts at writing to the pico before a deploy fails
+    #[clap(long, value_name = "N", default_value_t = 3, requires = "deploy")]
+    deploy_attempts: u32,
+
+    /// Wait up to SECONDS for a pico to be mounted before deploying
+    #[clap(long, value_name = "SECONDS", requires = "deploy")]
+    wait: Option<u32>,
+
+    /// Mount point of the pico drive to deploy to, needed when several are mounted
+    #[clap(long, value_name = "PATH", requires = "deploy")]
+    drive: Option<PathBuf>,
+
+    /// Deploy even when the drive is for another board than --family
+    #[clap(long, requires = "deploy")]
+    force: bool,
+
+    /// Turn warnings about the input or the target into errors
+    #[clap(long)]
+    strict: bool,
+
+    /// Board to convert for, by name or UF2 family id [default: rp2040, or the deploy drive's]
+    #[clap(long, env = "ELF2UF2_FAMILY", value_name = "FAMILY")]
+    family: Option<String>,
+
+    /// UF2 family id to write instead of the board's, which still decides the address ranges
+    #[clap(long, value_name = "ID", value_parser = parse_u32)]
+    family_id: Option<u32>,
+
+    /// Fail instead of warn when the first flash page is not a valid boot2 stage
+    #[clap(long)]
+    expect_boot2: bool,
+
+    /// Emit segments in the bootrom region instead of ignoring them
+    #[clap(long)]
+    include_rom: bool,
+
+    /// Append a CRC32 (zlib polynomial) of the flash image from its start up to this address, at
+    /// this address
+    #[clap(long, value_name = "ADDR", value_parser = parse_u32)]
+    append_crc32: Option<u32>,
+
+    /// Print the start of the entry point page decoded as a Cortex-M vector table
+    #[clap(long)]
+    dump_entry: bool,
+
+    /// Entry point RAM binaries must have, e.g. the trampoline a bootloader enters at, instead of
+    /// the start of their first page in main RAM
+    #[clap(long, value_name = "ADDR", value_parser = parse_u32)]
+    ram_entry: Option<u32>,
+
+    /// Check the Cortex-M vector table at this address: an initial SP in main RAM and a Thumb
+    /// reset handler within the image
+    #[clap(long, value_name = "ADDR", value_parser = parse_u32)]
+    vector_table: Option<u32>,
+
+    /// Remove blocks without any content, including the RP2040 flash sector padding. Only for
+    /// bootloaders that do not erase flash sectors based on the block number
+    #[clap(long)]
+    drop_empty_blocks: bool,
+
+    /// Only convert the parts of segments in these address ranges, given as START-END or
+    /// START:END
+    #[clap(long, value_name = "RANGE", value_parser = parse_address_range)]
+    include: Vec<Range<u32>>,
+
+    /// Leave out the parts of segments in these address ranges, given as START-END or START:END
+    #[clap(long, value_name = "RANGE", value_parser = parse_address_range)]
+    exclude: Vec<Range<u32>>,
+
+    /// Put the blocks of this UF2, e.g. a bootloader, ahead of the converted image
+    #[clap(long, value_name = "UF2")]
+    combine: Option<PathBuf>,
+
+    /// First start magic of every block, for bootloaders using non-standard magics
+    #[clap(long, global = true, value_name = "MAGIC", value_parser = parse_u32)]
+    magic_start0: Option<u32>,
+
+    /// Second start magic of every block
+    #[clap(long, global = true, value_name = "MAGIC", value_parser = parse_u32)]
+    magic_start1: Option<u32>,
+
+    /// End magic of every block
+    #[clap(long, global = true, value_name = "MAGIC", value_parser = parse_u32)]
+    magic_end: Option<u32>,
+
+    /// Inputs are raw binaries loaded at --load-address instead of ELFs
+    #[clap(long, requires = "load_address")]
+    binary: bool,
+
+    /// Address the start of a --binary input is loaded at
+    #[clap(long, value_name = "ADDR", value_parser = parse_u32, requires = "binary")]
+    load_address: Option<u32>,
+
+    /// Only print a summary of the conversion, nothing is written
+    #[clap(long, alias = "dry-run", conflicts_with = "deploy")]
+    summary_only: bool,
+
+    /// Before converting, report every segment rejected for the board instead of only the first
+    #[clap(long)]
+    analyze: bool,
+
+    /// Store the MD5 of its payload in every block, for bootloaders that check it
+    #[clap(long)]
+    md5: bool,
+
+    /// Byte written where the image has no contents, e.g. 0xff for the erased state of flash
+    #[clap(long, value_name = "BYTE", value_parser = parse_u8, default_value = "0")]
+    fill_byte: u8,
+
+    /// Byte filling the data region of every block past its payload
+    #[clap(long, value_name = "BYTE", value_parser = parse_u8, default_value = "0")]
+    data_pad_byte: u8,
+
+    /// Number the blocks from N, to append them to the blocks of another run
+    #[clap(long, value_name = "N", value_parser = parse_u32)]
+    block_no_base: Option<u32>,
+
+    /// Block count of the whole file the blocks are part of
+    #[clap(long, value_name = "M", value_parser = parse_u32)]
+    total_blocks: Option<u32>,
+
+    /// What to write for every input
+    #[clap(
+        long,
+        alias = "format",
+        value_enum,
+        default_value_t,
+        conflicts_with = "deploy"
+    )]
+    output_format: OutputFormat,
+
+    /// Largest --output-format bin image, gaps included, before the conversion is refused
+    #[clap(long, value_name = "BYTES", value_parser = parse_u32, default_value = "0x400000")]
+    max_bin_size: u32,
+
+    /// Keep what was written of an output when its conversion fails, instead of removing it
+    #[clap(long, global = true)]
+    keep_on_error: bool,
+
+    /// Write the output of every input to this directory
+    #[clap(long)]
+    output_dir: Option<PathBuf>,
+
+    /// Input files. A single input may be followed by its output file, .uf2 is added to the output
+    /// if it has no extension
+    #[clap(required = true, value_name = "INPUT")]
+    files: Vec<String>,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum OutputFormat {
+    /// Convert to UF2
+    #[default]
+    Uf2,
+    /// Copy the input ELF once it passes the same validation as a conversion
+    Elf,
+    /// Intel HEX of the pages the UF2 would write
+    Hex,
+    /// Flat binary of the pages the UF2 would write, gaps filled with --fill-byte
+    Bin,
+}
+
+impl OutputFormat {
+    /// Extension of outputs not named explicitly
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Uf2 => "uf2",
+            OutputFormat::Elf => "checked.elf",
+            OutputFormat::Hex => "hex",
+            OutputFormat::Bin => "bin",
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List connected UF2 devices and mounted UF2 drives
+    Devices,
+    /// Check that a UF2 file is well formed
+    Check {
+        /// UF2 file
+        input: PathBuf,
+        /// Also check that the UF2 holds every loadable segment of this ELF unchanged
+        #[clap(long, value_name = "ELF")]
+        verify_against: Option<PathBuf>,
+        /// Board the ELF was converted for [default: the board of the UF2 family, or rp2040]
+        #[clap(long, requires = "verify_against")]
+        family: Option<String>,
+    },
+    /// Write a UF2 without the blocks of one family, e.g. one half of a dual architecture image
+    DropFamily {
+        /// Board name or UF2 family id to remove
+        family: String,
+        /// UF2 file
+        input: PathBuf,
+        /// UF2 file to write the remaining blocks to
+        output: PathBuf,
+    },
+    /// Merge UF2 files into one, e.g. a bootloader and an application
+    Merge {
+        /// UF2 files, written in this order
+        #[clap(required = true, num_args = 2..)]
+        inputs: Vec<PathBuf>,
+        /// UF2 file to write
+        #[clap(short, long)]
+        output: PathBuf,
+    },
+    /// Write the blocks of every family of a UF2 to a file of its own
+    Split {
+        /// UF2 file, the families are written next to it as NAME.FAMILY.uf2
+        input: PathBuf,
+    },
+    /// List the boards that can be converted for
+    Boards,
+    /// Recover an ELF from the blocks of a UF2
+    ToElf {
+        /// UF2 file
+        input: PathBuf,
+        /// ELF file to write
+        output: PathBuf,
+    },
+    /// Print the address ranges and sizes a board converts with
+    Ranges {
+        /// Board name or UF2 family id [default: rp2040]
+        family: Option<String>,
+    },
+}
+
+/// What made a run fail, it decides the exit code. Invalid arguments exit with 2 from clap
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Failure {
+    /// A conversion, or anything not covered by the others
+    Conversion = 1,
+    /// A UF2 rejected by check, or an ELF rejected with --output-format elf
+    Validation = 3,
+    /// No drive to deploy to, or writing to it failed
+    Deploy = 4,
+    /// The serial connection after a deploy failed
+    #[cfg_attr(not(feature = "serial"), allow(dead_code))]
+    Serial = 5,
+}
+
+trait FailureExt<T> {
+    fn or_fail(self, failure: Failure) -> Result<T, (Failure, Box<dyn Error>)>;
+}
+
+impl<T, E: Into<Box<dyn Error>>> FailureExt<T> for Result<T, E> {
+    fn or_fail(self, failure: Failure) -> Result<T, (Failure, Box<dyn Error>)> {
+        self.map_err(|err| (failure, err.into()))
+    }
+}
+
+/// Product names accepted for --family, with the name of the board they are built on
+const BOARD_ALIASES: &[(&str, &str)] = &[
+    ("pico", "rp2040"),
+    ("pico-w", "rp2040"),
+    ("pico2", "rp2350"),
+    ("pico2-w", "rp2350"),
+];
+
+/// Finds a board by name, product name or UF2 family id, the RP2040 if none is given
+fn find_board(family: Option<&str>) -> Result<Box<dyn BoardInfo>, Box<dyn Error>> {
+    let Some(family) = family else {
+        return Ok(Box::new(RP2040));
+    };
+    let family = BOARD_ALIASES
+        .iter()
+        .find(|(alias, _)| alias.eq_ignore_ascii_case(family))
+        .map_or(family, |(_, name)| name);
+
+    let mut registry = BoardRegistry::default();
+    if let Some(board) = registry.take_by_name(family) {
+        return Ok(board);
+    }
 
-    /// Output file
-    output: Option<String>,
+    let names: Vec<&str> = registry.iter().map(|board| board.name()).collect();
+    let err = format!(
+        "Unknown family {family}, expected one of {}",
+        names.join(", ")
+    );
+    let family_id = parse_u32(family).ok();
+    registry
+        .into_iter()
+        .find(|board| Some(board.family_id()) == family_id)
+        .ok_or_else(|| err.into())
 }
 
 impl Opts {
-    fn output_path(&self) -> PathBuf {
-        if let Some(output) = &self.output {
-            Path::new(output).with_extension("uf2")
-        } else {
-            Path::new(&self.input).with_extension("uf2")
+    fn verbosity(&self) -> Verbosity {
+        match self.verbose {
+            0 => Verbosity::Quiet,
+            1 => Verbosity::Info,
+            2 => Verbosity::Debug,
+            _ => Verbosity::Trace,
+        }
+    }
+
+    /// The board selected with --family
+    fn board(&self) -> Result<Box<dyn BoardInfo>, Box<dyn Error>> {
+        find_board(self.family.as_deref())
+    }
+
+    /// The block magics, the standard ones unless overridden
+    fn magics(&self) -> Uf2Magics {
+        let standard = Uf2Magics::default();
+        Uf2Magics {
+            start0: self.magic_start0.unwrap_or(standard.start0),
+            start1: self.magic_start1.unwrap_or(standard.start1),
+            end: self.magic_end.unwrap_or(standard.end),
+        }
+    }
+
+    /// What to convert with, the UF2 to --combine is read here
+    fn conversion_options(&self) -> Result<ConversionOptions, Box<dyn Error>> {
+        let combine = match &self.combine {
+            Some(path) => Some(fs::read(path).map_err(|err| format!("{}: {err}", path.display()))?),
+            None => None,
+        };
+
+        Ok(ConversionOptions {
+            verbosity: self.verbosity(),
+            strict: self.strict,
+            family_id: self.family_id,
+            expect_boot2: self.expect_boot2,
+            include_rom: self.include_rom,
+            append_crc32: self.append_crc32,
+            dump_entry: self.dump_entry,
+            ram_entry: self.ram_entry,
+            vector_table: self.vector_table,
+            drop_empty_blocks: self.drop_empty_blocks,
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            combine,
+            magics: self.magics(),
+            analyze: self.analyze,
+            md5: self.md5,
+            fill_byte: self.fill_byte,
+            data_pad_byte: self.data_pad_byte,
+            block_no_base: self.block_no_base,
+            total_blocks: self.total_blocks,
+        })
+    }
+
+    /// Pairs every input with its output path. Two files are an input and its output, unless the
+    /// second one is an .elf too when converting to UF2, or an output directory is given
+    fn conversions(&self) -> Vec<(PathBuf, PathBuf)> {
+        let extension = self.output_format.extension();
+        let is_elf = |file: &str| {
+            Path::new(file)
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("elf"))
+        };
+
+        if let [input, output] = &self.files[..] {
+            if self.output_dir.is_none()
+                && (self.output_format == OutputFormat::Elf || !is_elf(output))
+            {
+                let output = Path::new(output);
+                let output = if output.extension().is_some() || output == Path::new(STDOUT_PATH) {
+                    output.to_owned()
+                } else {
+                    output.with_extension(extension)
+                };
+                return vec![(PathBuf::from(input), output)];
+            }
         }
+
+        self.files
+            .iter()
+            .map(|input| {
+                let input = PathBuf::from(input);
+                let output = input.with_extension(extension);
+                let output = match &self.output_dir {
+                    Some(dir) => dir.join(output.file_name().unwrap_or_default()),
+                    None => output,
+                };
+                (input, output)
+            })
+            .collect()
+    }
+
+    /// True when the only output is written to stdout
+    fn writes_to_stdout(&self) -> bool {
+        matches!(&self.conversions()[..], [(_, output)] if output == Path::new(STDOUT_PATH))
     }
 
     fn global() -> &'static Opts {
@@ -68,293 +463,1167 @@ impl Opts {
     }
 }
 
+/// An output path writing to stdout instead of a file
+const STDOUT_PATH: &str = "-";
+
+/// How long to wait for the drive after --reboot when --wait is not given
+#[cfg(feature = "serial")]
+const REBOOT_WAIT_SECONDS: u32 = 10;
+
 static OPTS: OnceLock<Opts> = OnceLock::new();
 
-fn elf2uf2(mut input: impl Read + Seek, mut output: impl Write) -> Result<(), Box<dyn Error>> {
-    let eh = Elf32Header::from_read(&mut input)?;
+/// Parses a decimal or 0x prefixed hexadecimal number
+fn parse_u32(s: &str) -> Result<u32, String> {
+    let result = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u32::from_str_radix(&hex.replace('_', ""), 16)
+    } else {
+        s.replace('_', "").parse()
+    };
+
+    result.map_err(|err| format!("{s:?} is not a 32 bit number: {err}"))
+}
 
-    let entries = eh.read_elf32_ph_entries(&mut input)?;
+/// Parses a decimal or 0x prefixed hexadecimal byte
+fn parse_u8(s: &str) -> Result<u8, String> {
+    u8::try_from(parse_u32(s)?).map_err(|err| format!("{s:?} is not a byte: {err}"))
+}
 
-    let ram_style = eh
-        .is_ram_binary(&entries)
-        .ok_or("entry point is not in mapped part of file".to_string())?;
+/// Parses a START-END or START:END address range, END excluded
+fn parse_address_range(s: &str) -> Result<Range<u32>, String> {
+    let (start, end) = s
+        .split_once(['-', ':'])
+        .ok_or_else(|| format!("{s:?} is not a START-END range"))?;
+    let range = parse_u32(start.trim())?..parse_u32(end.trim())?;
 
-    if Opts::global().verbose {
-        if ram_style {
-            println!("Detected RAM binary");
-        } else {
-            println!("Detected FLASH binary");
+    if range.is_empty() {
+        return Err(format!("{s:?} is an empty range"));
+    }
+
+    Ok(range)
+}
+
+/// What the INFO_UF2.TXT of a mounted UF2 drive says about it
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Uf2DriveInfo {
+    /// The first line, naming the bootloader and its version
+    bootloader: String,
+    model: Option<String>,
+    board_id: Option<String>,
+}
+
+impl Uf2DriveInfo {
+    fn parse(info: &str) -> Self {
+        let mut lines = info.lines();
+        let mut parsed = Self {
+            bootloader: lines.next().unwrap_or_default().trim().to_owned(),
+            ..Default::default()
+        };
+
+        for line in lines {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = Some(value.trim().to_owned());
+            match key.trim() {
+                "Model" => parsed.model = value,
+                "Board-ID" => parsed.board_id = value,
+                _ => (),
+            }
         }
+
+        parsed
     }
 
-    let valid_ranges = if ram_style {
-        RP2040_ADDRESS_RANGES_RAM
-    } else {
-        RP2040_ADDRESS_RANGES_FLASH
-    };
+    fn read(drive: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::parse(&fs::read_to_string(
+            drive.join("INFO_UF2.TXT"),
+        )?))
+    }
+
+    /// The board with this Board-ID
+    fn board(&self) -> Result<Box<dyn BoardInfo>, Box<dyn Error>> {
+        let board_id = self
+            .board_id
+            .as_deref()
+            .ok_or("INFO_UF2.TXT has no Board-ID")?;
 
-    let mut pages = valid_ranges.check_elf32_ph_entries(&entries)?;
+        BoardIter::new()
+            .find(|board| board.is_uf2_board_id(board_id))
+            .ok_or_else(|| format!("Unknown Board-ID {board_id}").into())
+    }
 
-    if pages.is_empty() {
-        return Err("The input file has no memory pages".into());
+    /// Fails when the drive is known to be for another board than `board`
+    fn check_board(&self, board: &dyn BoardInfo) -> Result<(), String> {
+        match self.board() {
+            Ok(drive_board) if drive_board.family_id() != board.family_id() => Err(format!(
+                "The drive is a {} ({}), not a {}",
+                self.model.as_deref().unwrap_or("UF2 drive"),
+                drive_board.name(),
+                board.name()
+            )),
+            _ => Ok(()),
+        }
     }
+}
 
-    if ram_style {
-        let mut expected_ep_main_ram = u32::MAX;
-        let mut expected_ep_xip_sram = u32::MAX;
+/// The board of a mounted UF2 drive, from the Board-ID in its INFO_UF2.TXT
+fn drive_board(drive: &Path) -> Result<Box<dyn BoardInfo>, Box<dyn Error>> {
+    Uf2DriveInfo::read(drive)?.board()
+}
 
-        #[allow(clippy::manual_range_contains)]
-        pages.keys().copied().for_each(|addr| {
-            if addr >= MAIN_RAM_START && addr <= MAIN_RAM_END {
-                expected_ep_main_ram = expected_ep_main_ram.min(addr) | 0x1;
-            } else if addr >= XIP_SRAM_START && addr < XIP_SRAM_END {
-                expected_ep_xip_sram = expected_ep_xip_sram.min(addr) | 0x1;
+/// The board to deploy to. Without --family it is inferred from the drive, then from the
+/// connected USB devices with the usb feature, falling back to the RP2040 unless --strict
+fn deploy_board(drive: &Path) -> Result<Box<dyn BoardInfo>, Box<dyn Error>> {
+    if Opts::global().family.is_some() {
+        let board = Opts::global().board()?;
+        let Ok(info) = Uf2DriveInfo::read(drive) else {
+            return Ok(board);
+        };
+        if let Err(err) = info.check_board(&*board) {
+            if !Opts::global().force {
+                return Err(format!("{err}, use --force to deploy anyway").into());
             }
-        });
+            eprintln!("Warning: {err}");
+        }
+        return Ok(board);
+    }
+
+    let err = match drive_board(drive) {
+        Ok(board) => {
+            if Opts::global().verbosity() >= Verbosity::Info {
+                println!("Detected {} drive", board.name());
+            }
+            return Ok(board);
+        }
+        Err(err) => err,
+    };
+
+    #[cfg(feature = "usb")]
+    if let Ok(Some(board)) = usb::detect_connected_board() {
+        if Opts::global().verbosity() >= Verbosity::Info {
+            println!("Detected {} USB device", board.name());
+        }
+        return Ok(board);
+    }
+
+    if Opts::global().strict {
+        return Err(format!("Unable to detect the board of the drive: {err}").into());
+    }
+    eprintln!("Warning: unable to detect the board of the drive, assuming rp2040: {err}");
+    Ok(Box::new(RP2040))
+}
+
+/// Mount points of all drives that look like a UF2 bootloader
+fn find_pico_drives() -> Vec<PathBuf> {
+    let disks = Disks::new_with_refreshed_list();
 
-        let expected_ep = if expected_ep_main_ram != u32::MAX {
-            expected_ep_main_ram
+    disks
+        .iter()
+        .map(|disk| disk.mount_point())
+        .filter(|mount| mount.join("INFO_UF2.TXT").is_file())
+        .map(|mount| mount.to_owned())
+        .collect()
+}
+
+fn list_devices() -> Result<(), Box<dyn Error>> {
+    #[cfg(feature = "usb")]
+    for (device, board) in usb::list_devices()? {
+        println!(
+            "Bus {:03} Device {:03}: ID {:04x}:{:04x} {}",
+            device.bus_number,
+            device.address,
+            device.vendor_id,
+            device.product_id,
+            board
+                .as_ref()
+                .map(|board| board.name())
+                .unwrap_or("unknown")
+        );
+    }
+
+    for drive in find_pico_drives() {
+        let model = Uf2DriveInfo::read(&drive)
+            .ok()
+            .and_then(|info| info.model)
+            .unwrap_or_else(|| "unknown".to_owned());
+        println!("UF2 drive {}: {model}", drive.to_string_lossy());
+    }
+
+    Ok(())
+}
+
+/// Converts one input. Errors name the input
+fn convert(input_path: &Path, output: &Path, board: &dyn BoardInfo) -> Result<(), Box<dyn Error>> {
+    convert_input(input_path, output, board)
+        .map_err(|err| format!("{}: {err}", input_path.display()).into())
+}
+
+fn convert_input(
+    input_path: &Path,
+    output: &Path,
+    board: &dyn BoardInfo,
+) -> Result<(), Box<dyn Error>> {
+    let mut reporter: Box<dyn ProgressReporter> =
+        if Opts::global().verbosity() == Verbosity::Quiet && Opts::global().deploy {
+            Box::new(ProgressBarReporter::default())
         } else {
-            expected_ep_xip_sram
+            Box::new(NoProgress)
         };
+    let options = Opts::global().conversion_options()?;
 
-        if expected_ep == expected_ep_xip_sram {
-            return Err("B0/B1 Boot ROM does not support direct entry into XIP_SRAM".into());
-        } else if eh.entry != expected_ep {
-            #[allow(clippy::unnecessary_cast)]
-            return Err(format!(
-                "A RAM binary should have an entry point at the beginning: {:#08x} (not {:#08x})",
-                expected_ep, eh.entry as u32
-            )
-            .into());
+    if Opts::global().summary_only {
+        let input = open_input(input_path, board)?;
+        let stats = elf2uf2(input, io::sink(), reporter.as_mut(), board, &options)?;
+        print_summary(input_path, &stats, board);
+        return Ok(());
+    }
+
+    if Opts::global().output_format == OutputFormat::Elf {
+        if Opts::global().binary {
+            return Err("--output-format elf copies ELF inputs, not --binary ones".into());
+        }
+        if fs::canonicalize(output)
+            .is_ok_and(|output| fs::canonicalize(input_path).is_ok_and(|input| input == output))
+        {
+            return Err("The output would overwrite the input".into());
         }
-        const_assert!(0 == (MAIN_RAM_START & (PAGE_SIZE - 1)));
 
-        // TODO: check vector table start up
-        // currently don't require this as entry point is now at the start, we don't know where reset vector is
-    } else {
-        // Fill in empty dummy uf2 pages to align the binary to flash sectors (except for the last sector which we don't
-        // need to pad, and choose not to to avoid making all SDK UF2s bigger)
-        // That workaround is required because the bootrom uses the block number for erase sector calculations:
-        // https://github.com/raspberrypi/pico-bootrom/blob/c09c7f08550e8a36fc38dc74f8873b9576de99eb/bootrom/virtual_disk.c#L205
-
-        let touched_sectors: HashSet<u32> = pages
-            .keys()
-            .map(|addr| addr / FLASH_SECTOR_ERASE_SIZE)
-            .collect();
+        let input = open_input(input_path, board)?;
+        elf2uf2(input, io::sink(), reporter.as_mut(), board, &options)?;
+        if output == Path::new(STDOUT_PATH) {
+            io::copy(&mut File::open(input_path)?, &mut io::stdout().lock())?;
+        } else {
+            fs::copy(input_path, output)?;
+        }
+        return Ok(());
+    }
 
-        let last_page_addr = *pages.last_key_value().unwrap().0;
-        for sector in touched_sectors {
-            let mut page = sector * FLASH_SECTOR_ERASE_SIZE;
+    // Nothing to clean up on failure, whatever was written is already on its way down the pipe
+    if output == Path::new(STDOUT_PATH) {
+        let input = open_input(input_path, board)?;
+        elf2output(
+            input,
+            input_path,
+            io::stdout().lock(),
+            reporter.as_mut(),
+            board,
+            &options,
+            &|_| Ok(()),
+        )?;
+        return Ok(());
+    }
 
-            while page < (sector + 1) * FLASH_SECTOR_ERASE_SIZE {
-                if page < last_page_addr && !pages.contains_key(&page) {
-                    pages.insert(page, Vec::new());
+    // The bootloader takes the blocks as they are written, so a deploy can't go through a rename
+    let stats = if Opts::global().deploy {
+        let input = open_input(input_path, board)?;
+        let check_len = |len| {
+            check_free_space(output, len)?;
+            println!("Transfering program to pico");
+            Ok(())
+        };
+        let mut writer = CopyingWriter {
+            output: File::create(output)?,
+            copy: Opts::global().verify.then(Vec::new),
+        };
+        match elf2uf2_input(
+            input,
+            input_path,
+            &mut writer,
+            reporter.as_mut(),
+            board,
+            &options,
+            &check_len,
+        ) {
+            Ok(stats) => {
+                if let Some(written) = writer.copy {
+                    verify_deployed(output, &written)?;
+                }
+                stats
+            }
+            Err(err) => {
+                // The drive may already be gone, that must not hide why the deploy failed
+                if let Err(remove_err) = remove_failed_output(output) {
+                    eprintln!(
+                        "Warning: unable to remove {}: {remove_err}",
+                        output.display()
+                    );
                 }
-                page += PAGE_SIZE;
+                return Err(err);
             }
         }
+    } else {
+        elf2uf2_file(input_path, output, reporter.as_mut(), board, &options)?.1
+    };
+
+    if Opts::global().verbosity() >= Verbosity::Info {
+        println!(
+            "Wrote {} blocks, {} of them padding ({} bytes)",
+            stats.blocks, stats.padding_blocks, stats.padding_bytes
+        );
     }
 
-    let mut block_header = Uf2BlockHeader {
-        magic_start0: UF2_MAGIC_START0,
-        magic_start1: UF2_MAGIC_START1,
-        flags: UF2_FLAG_FAMILY_ID_PRESENT,
-        target_addr: 0,
-        payload_size: PAGE_SIZE,
-        block_no: 0,
-        num_blocks: pages.len().assert_into(),
-        file_size: RP2040_FAMILY_ID,
-    };
+    Ok(())
+}
+
+/// Opens an input to convert for `board`, an ELF or with --binary a raw binary wrapped in one
+fn open_input(path: &Path, board: &dyn BoardInfo) -> Result<Box<dyn ReadSeek>, Box<dyn Error>> {
+    let input = BufReader::new(File::open(path)?);
+
+    match Opts::global().load_address {
+        Some(load_address) if Opts::global().binary => {
+            Ok(Box::new(binary_elf(input, load_address, board)?))
+        }
+        _ => Ok(Box::new(input)),
+    }
+}
+
+/// A raw binary as an ELF with a single segment at `load_address`, so it is converted and padded
+/// like any other input. The binary must fit in the flash or RAM ranges of `board` that take
+/// contents
+fn binary_elf(
+    mut input: impl Read,
+    load_address: u32,
+    board: &dyn BoardInfo,
+) -> Result<io::Cursor<Vec<u8>>, Box<dyn Error>> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    if data.is_empty() {
+        return Err("The binary is empty".into());
+    }
+    if u64::from(load_address) + data.len() as u64 > 1 << 32 {
+        return Err(format!(
+            "The binary of {} bytes at {load_address:#08x} extends past 4 GiB",
+            data.len()
+        )
+        .into());
+    }
+
+    let params = ConversionParams::from(board);
+    let size: u32 = data.len().assert_into();
+    params
+        .flash_ranges
+        .check_address_range(load_address, load_address, size, false, Verbosity::Quiet)
+        .or_else(|_| {
+            params.ram_ranges.check_address_range(
+                load_address,
+                load_address,
+                size,
+                false,
+                Verbosity::Quiet,
+            )
+        })?;
+
+    let mut elf = Vec::new();
+    uf2toelf::write_elf(&[(load_address, data)], &mut elf)?;
+    Ok(io::Cursor::new(elf))
+}
+
+/// Writes to `output`, keeping a copy of everything written when there is a `copy`
+struct CopyingWriter<W> {
+    output: W,
+    copy: Option<Vec<u8>>,
+}
+
+impl<W: Write> Write for CopyingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.output.write(buf)?;
+        if let Some(copy) = &mut self.copy {
+            copy.extend_from_slice(&buf[..written]);
+        }
+        Ok(written)
+    }
 
-    let mut block_data: Uf2BlockData = [0; 476];
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
 
-    let block_footer = Uf2BlockFooter {
-        magic_end: UF2_MAGIC_END,
+/// Reads a deployed UF2 back and compares the number and addresses of its blocks with those
+/// written. The bootloader reboots as soon as it has every block, so a drive that is gone before
+/// the read finishes is only a warning
+fn verify_deployed(path: &Path, written: &[u8]) -> Result<(), Box<dyn Error>> {
+    let read_back: io::Result<Vec<Uf2Block>> =
+        File::open(path).and_then(|file| Uf2Reader::new(BufReader::new(file)).collect());
+    let blocks = match read_back {
+        Ok(blocks) => blocks,
+        Err(err) => {
+            eprintln!(
+                "Warning: unable to verify {}, the drive went away while reading it back: {err}",
+                path.display()
+            );
+            return Ok(());
+        }
     };
 
-    if Opts::global().deploy {
-        println!("Transfering program to pico");
+    let expected = Uf2Reader::new(written).collect::<io::Result<Vec<Uf2Block>>>()?;
+    if blocks.len() != expected.len() {
+        return Err(format!(
+            "Verify failed, read back {} blocks of the {} written",
+            blocks.len(),
+            expected.len()
+        )
+        .into());
     }
 
-    let mut pb = if !Opts::global().verbose && Opts::global().deploy {
-        Some(ProgressBar::new((pages.len() * 512).assert_into()))
+    for (i, (block, expected)) in blocks.iter().zip(&expected).enumerate() {
+        let (header, expected) = (block.header, expected.header);
+        if header.num_blocks != expected.num_blocks || header.target_addr != expected.target_addr {
+            return Err(format!(
+                "Verify failed, block {i} reads back as one of {} blocks at {:#010x}, one of {} at {:#010x} was written",
+                { header.num_blocks },
+                { header.target_addr },
+                { expected.num_blocks },
+                { expected.target_addr }
+            )
+            .into());
+        }
+    }
+
+    if Opts::global().verbosity() >= Verbosity::Info {
+        println!("Verified {} blocks", blocks.len());
+    }
+
+    Ok(())
+}
+
+fn print_summary(input: &Path, stats: &ConversionStats, board: &dyn BoardInfo) {
+    let entry_point = stats.entry_point;
+
+    println!("{:<16}{}", "Input", input.display());
+    println!("{:<16}{}", "Board", board.name());
+    println!(
+        "{:<16}{}",
+        "Binary",
+        if entry_point.ram_binary {
+            "RAM"
+        } else {
+            "FLASH"
+        }
+    );
+    println!(
+        "{:<16}{:#010x} ({:#010x} in {})",
+        "Entry point", entry_point.entry, entry_point.effective_entry, entry_point.range
+    );
+    if let Some(range) = &stats.address_range {
+        println!(
+            "{:<16}{:#010x} to {:#010x}",
+            "Addresses", range.start, range.end
+        );
+    }
+    println!("{:<16}{}", "Blocks", stats.blocks);
+    println!(
+        "{:<16}{} ({} bytes)",
+        "Padding blocks", stats.padding_blocks, stats.padding_bytes
+    );
+    println!(
+        "{:<16}{} bytes",
+        "Payload",
+        stats.blocks * board.page_size() as usize
+    );
+    println!("{:<16}{} bytes", "UF2 size", stats.blocks * UF2_BLOCK_SIZE);
+}
+
+/// Converts the ELF at `input_path` to a UF2 at `output`. The UF2 is written next to `output` and
+/// renamed into place once complete, so a failed conversion leaves no partial file behind
+fn elf2uf2_file(
+    input_path: &Path,
+    output: &Path,
+    reporter: &mut dyn ProgressReporter,
+    board: &dyn BoardInfo,
+    options: &ConversionOptions,
+) -> Result<(PathBuf, ConversionStats), Box<dyn Error>> {
+    let input = open_input(input_path, board)?;
+
+    let mut partial = output.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+
+    let result = File::create(&partial)
+        .map_err(Into::into)
+        .and_then(|file| {
+            let check_len = |len| check_free_space(&partial, len);
+            elf2output(
+                input,
+                input_path,
+                BufWriter::new(file),
+                reporter,
+                board,
+                options,
+                &check_len,
+            )
+        })
+        .and_then(|stats| {
+            fs::rename(&partial, output)?;
+            Ok(stats)
+        });
+
+    match result {
+        Ok(stats) => Ok((output.to_owned(), stats)),
+        Err(err) => {
+            remove_failed_output(&partial).ok();
+            Err(err)
+        }
+    }
+}
+
+/// Removes what was written of an output before its conversion failed. With --keep-on-error it
+/// is kept for inspection instead
+fn remove_failed_output(output: &Path) -> io::Result<()> {
+    if Opts::global().keep_on_error {
+        if output.exists() {
+            eprintln!("Kept the partial output {}", output.display());
+        }
+        return Ok(());
+    }
+    fs::remove_file(output)
+}
+
+/// Converts the opened input at `input_path` like `elf2uf2_checked`. With the parallel feature
+/// every worker reads the pages through a file handle of its own, --binary inputs are already in
+/// memory and are read serially
+fn elf2uf2_input(
+    input: Box<dyn ReadSeek>,
+    #[cfg_attr(not(feature = "parallel"), allow(unused_variables))] input_path: &Path,
+    output: impl Write,
+    reporter: &mut dyn ProgressReporter,
+    board: &dyn BoardInfo,
+    options: &ConversionOptions,
+    check_len: &dyn Fn(usize) -> Result<(), Box<dyn Error>>,
+) -> Result<ConversionStats, Box<dyn Error>> {
+    #[cfg(feature = "parallel")]
+    if !Opts::global().binary {
+        let open = || -> io::Result<Box<dyn ReadSeek>> {
+            Ok(Box::new(BufReader::new(File::open(input_path)?)))
+        };
+        return elf2uf2_parallel(input, &open, output, reporter, board, options, check_len);
+    }
+
+    elf2uf2_checked(input, output, reporter, board, options, check_len)
+}
+
+/// Converts like `elf2uf2_checked`, but writes the output in the --output-format. Intel HEX and
+/// flat binaries hold the payloads of the UF2 blocks
+fn elf2output(
+    input: Box<dyn ReadSeek>,
+    input_path: &Path,
+    mut output: impl Write,
+    reporter: &mut dyn ProgressReporter,
+    board: &dyn BoardInfo,
+    options: &ConversionOptions,
+    check_len: &dyn Fn(usize) -> Result<(), Box<dyn Error>>,
+) -> Result<ConversionStats, Box<dyn Error>> {
+    let format = Opts::global().output_format;
+    if !matches!(format, OutputFormat::Hex | OutputFormat::Bin) {
+        let stats = elf2uf2_input(
+            input,
+            input_path,
+            &mut output,
+            reporter,
+            board,
+            options,
+            check_len,
+        )?;
+        output.flush()?;
+        return Ok(stats);
+    }
+
+    let mut uf2 = Vec::new();
+    let stats = elf2uf2_input(
+        input, input_path, &mut uf2, reporter, board, options, check_len,
+    )?;
+
+    let blocks = Uf2Reader::new(&uf2[..]).collect::<io::Result<Vec<Uf2Block>>>()?;
+    let segments = blocks.iter().map(|block| {
+        let payload_size: usize = { block.header.payload_size }.assert_into();
+        ({ block.header.target_addr }, &block.data[..payload_size])
+    });
+    if format == OutputFormat::Hex {
+        ihex::write_ihex(segments, stats.entry_point.entry, output)?;
     } else {
-        None
+        let max_size = Opts::global().max_bin_size.into();
+        flat::write_flat(segments, options.fill_byte, max_size, output)?;
+    }
+
+    Ok(stats)
+}
+
+/// Fails when the filesystem `output` is on is known to lack room for `len` bytes. When the
+/// free space can't be determined it is assumed to be enough
+fn check_free_space(output: &Path, len: usize) -> Result<(), Box<dyn Error>> {
+    let dir = match output.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Ok(dir) = fs::canonicalize(dir) else {
+        return Ok(());
     };
 
-    if let Some(pb) = &mut pb {
-        pb.set_units(Units::Bytes);
+    let disks = Disks::new_with_refreshed_list();
+    let Some(disk) = disks
+        .iter()
+        .filter(|disk| dir.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+    else {
+        return Ok(());
+    };
+
+    if disk.available_space() < len as u64 {
+        return Err(format!(
+            "Not enough space for {len}-byte UF2 on {}, {} bytes available",
+            disk.mount_point().display(),
+            disk.available_space()
+        )
+        .into());
     }
 
-    let last_page_num = pages.len() - 1;
+    Ok(())
+}
+
+fn to_elf(input: &Path, output: &Path) -> Result<(), Box<dyn Error>> {
+    let input = BufReader::new(File::open(input)?);
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    let result = uf2toelf::uf2toelf(input, &mut writer, Opts::global().magics())
+        .and_then(|()| Ok(writer.flush()?));
+    if result.is_err() {
+        drop(writer);
+        remove_failed_output(output).ok();
+    }
 
-    for (page_num, (target_addr, fragments)) in pages.into_iter().enumerate() {
-        block_header.target_addr = target_addr;
-        block_header.block_no = page_num.assert_into();
+    result
+}
+
+fn list_boards() {
+    println!(
+        "{:<12}{:<12}{:<12}{:<12}Aliases",
+        "Board", "Family id", "Page size", "Erase size"
+    );
+    for board in BoardIter::new() {
+        let aliases: Vec<&str> = BOARD_ALIASES
+            .iter()
+            .filter(|(_, name)| *name == board.name())
+            .map(|(alias, _)| *alias)
+            .collect();
+        println!(
+            "{:<12}{:<12}{:<12}{:<12}{}",
+            board.name(),
+            format!("{:#010x}", board.family_id()),
+            board.page_size(),
+            board.flash_sector_erase_size(),
+            aliases.join(", ")
+        );
+    }
+}
 
-        #[allow(clippy::unnecessary_cast)]
-        if Opts::global().verbose {
+fn print_ranges(board: &dyn BoardInfo) -> Result<(), Box<dyn Error>> {
+    let params = ConversionParams::from(board);
+    let locations = params.locations;
+    let bounds = |range: Option<Range<u32>>| {
+        range.map_or("-".to_string(), |range| {
+            format!("{:#010x} {:#010x}", range.start, range.end)
+        })
+    };
+
+    println!("{:<16}{}", "Board", board.name());
+    println!("{:<16}{:#010x}", "Family id", params.family_id);
+    println!("{:<16}{}", "Page size", params.page_size);
+    println!("{:<16}{}", "Erase size", params.erase_size);
+    println!(
+        "{:<16}{}",
+        "Flash start",
+        locations
+            .flash_start
+            .map_or("-".to_string(), |start| format!("{start:#010x}"))
+    );
+    println!("{:<16}{}", "Main RAM", bounds(locations.main_ram()?));
+    println!("{:<16}{}", "XIP SRAM", bounds(locations.xip_sram()?));
+    println!("{:<16}{}", "Bootrom", bounds(locations.rom()?));
+
+    for (binary, ranges) in [("Flash", params.flash_ranges), ("RAM", params.ram_ranges)] {
+        println!();
+        println!("{binary} binaries");
+        for range in ranges {
             println!(
-                "Page {} / {} {:#08x}",
-                block_header.block_no as u32,
-                block_header.num_blocks as u32,
-                block_header.target_addr as u32
+                "  {:<14}{:#010x} {:#010x}",
+                format!("{:?}", range.typ),
+                range.from,
+                range.to
             );
         }
+    }
 
-        block_data.iter_mut().for_each(|v| *v = 0);
-
-        realize_page(&mut input, &fragments, &mut block_data)?;
+    Ok(())
+}
 
-        output.write_all(block_header.as_bytes())?;
-        output.write_all(block_data.as_bytes())?;
-        output.write_all(block_footer.as_bytes())?;
+fn check(input: &Path, elf: Option<&Path>, family: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let report = validate_uf2(BufReader::new(File::open(input)?), Opts::global().magics())?;
 
-        if page_num != last_page_num {
-            if let Some(pb) = &mut pb {
-                pb.add(512);
-            }
+    println!("{} blocks", report.blocks);
+    if let Some(range) = &report.address_range {
+        println!("Addresses {:#010x} to {:#010x}", range.start, range.end);
+    }
+    if report.out_of_order {
+        println!("Blocks are not in block number order");
+    }
+    if Opts::global().verbosity() >= Verbosity::Debug {
+        for block in &report.block_summaries {
+            println!(
+                "Block {} at {:#010x}, {} bytes, flags {:#010x}",
+                block.block_no, block.target_addr, block.payload_size, block.flags
+            );
         }
     }
+    for (family, summary) in &report.families {
+        let board = family.and_then(BoardIter::find_by_family_id);
+        println!(
+            "Family {}{}: {} blocks, {} payload bytes",
+            family.map_or("none".to_string(), |id| format!("{id:#010x}")),
+            board.map_or(String::new(), |board| format!(" ({})", board.name())),
+            summary.blocks,
+            summary.payload_bytes
+        );
+    }
+    for problem in &report.problems {
+        println!("{problem}");
+    }
+
+    if !report.is_valid() {
+        return Err(format!("{} problems found", report.problems.len()).into());
+    }
 
-    // Drop the output before the progress bar is allowd to finish
-    drop(output);
+    if let Some(elf) = elf {
+        let board = match family {
+            Some(family) => find_board(Some(family))?,
+            None => match report.families.keys().next() {
+                Some(&Some(family_id)) => {
+                    BoardIter::find_by_family_id(family_id).map_or_else(|| find_board(None), Ok)?
+                }
+                _ => find_board(None)?,
+            },
+        };
 
-    if let Some(pb) = &mut pb {
-        pb.add(512);
+        verify_uf2_against_elf(
+            BufReader::new(File::open(input)?),
+            BufReader::new(File::open(elf)?),
+            &*board,
+            Opts::global().magics(),
+        )
+        .map_err(|err| {
+            format!(
+                "{} does not match {}: {err}",
+                input.display(),
+                elf.display()
+            )
+        })?;
+        println!("Holds every loadable segment of {}", elf.display());
     }
 
     Ok(())
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    OPTS.set(Opts::parse()).unwrap();
+/// Checks that the blocks of `uf2` hold every byte the loadable segments of `elf` put in the
+/// memory `board` converts for. Bytes of the blocks not from the ELF, such as padding, are not
+/// checked. Fails with the lowest address that differs
+fn verify_uf2_against_elf(
+    uf2: impl Read,
+    mut elf: impl Read + Seek,
+    board: &dyn BoardInfo,
+    magics: Uf2Magics,
+) -> Result<(), Box<dyn Error>> {
+    let mut payloads = BTreeMap::new();
+    for block in Uf2Reader::new(uf2) {
+        let block = block?;
+        if block.magics() != magics {
+            return Err(format!("Block {} has bad magic", payloads.len()).into());
+        }
+        let payload_size: usize = { block.header.payload_size }.assert_into();
+        let payload = &block.data[..payload_size.min(block.data.len())];
+        payloads.insert(block.header.target_addr, payload.to_vec());
+    }
 
-    #[cfg(feature = "serial")]
-    let serial_ports_before = serialport::available_ports()?;
+    let eh = Elf32Header::from_read(&mut elf)?;
+    let mut entries = eh.read_elf32_ph_entries(&mut elf)?;
+    entries.sort_by_key(|entry| entry.paddr);
+
+    let ranges: Vec<&AddressRange> = board
+        .address_ranges_flash()
+        .iter()
+        .chain(board.address_ranges_ram())
+        .filter(|range| range.typ == AddressRangeType::Contents)
+        .collect();
+
+    for entry in entries {
+        let size = entry.filez.min(entry.memsz);
+        if entry.typ != PT_LOAD
+            || size == 0
+            || !ranges
+                .iter()
+                .any(|range| range.contains_span(entry.paddr, size))
+        {
+            continue;
+        }
 
-    let mut deployed_path = None;
-    let input = BufReader::new(File::open(&Opts::global().input)?);
+        let mut data = vec![0; size.assert_into()];
+        elf.seek(SeekFrom::Start(entry.offset.into()))?;
+        elf.read_exact(&mut data)?;
+
+        for (addr, &expected) in (entry.paddr..).zip(&data) {
+            let actual = payloads
+                .range(..=addr)
+                .next_back()
+                .and_then(|(&start, payload)| payload.get((addr - start) as usize));
+            match actual {
+                Some(&actual) if actual == expected => (),
+                Some(&actual) => {
+                    return Err(format!(
+                        "{addr:#010x} is {actual:#04x}, the ELF has {expected:#04x}"
+                    )
+                    .into())
+                }
+                None => return Err(format!("{addr:#010x} is in no block").into()),
+            }
+        }
+    }
 
-    let output = if Opts::global().deploy {
-        let disks = Disks::new_with_refreshed_list();
+    Ok(())
+}
 
-        let mut pico_drive = None;
-        for disk in &disks {
-            let mount = disk.mount_point();
+fn drop_family_blocks(family: &str, input: &Path, output: &Path) -> Result<(), Box<dyn Error>> {
+    let family_id = match parse_u32(family) {
+        Ok(family_id) => family_id,
+        Err(_) => find_board(Some(family))?.family_id(),
+    };
+    let input = BufReader::new(File::open(input)?);
+
+    // Written next to the output like a conversion, so the input may also be the output
+    let mut partial = output.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+
+    let result = File::create(&partial)
+        .map_err(Into::into)
+        .and_then(|file| {
+            write_without_family(
+                input,
+                BufWriter::new(file),
+                family_id,
+                Opts::global().magics(),
+            )
+        })
+        .and_then(|counts| {
+            fs::rename(&partial, output)?;
+            Ok(counts)
+        });
 
-            if mount.join("INFO_UF2.TXT").is_file() {
-                println!("Found pico uf2 disk {}", &mount.to_string_lossy());
-                pico_drive = Some(mount.to_owned());
-                break;
-            }
+    match result {
+        Ok((dropped, kept)) => {
+            println!("Dropped {dropped} blocks, {kept} left");
+            Ok(())
         }
+        Err(err) => {
+            remove_failed_output(&partial).ok();
+            Err(err)
+        }
+    }
+}
 
-        if let Some(pico_drive) = pico_drive {
-            deployed_path = Some(pico_drive.join("out.uf2"));
-            File::create(deployed_path.as_ref().unwrap())?
-        } else {
-            return Err("Unable to find mounted pico".into());
+/// Streams the blocks of `input` to `output` without those of `family_id`, renumbered within
+/// their own family. The counts are only known at the end, so they are patched into `output`
+/// afterwards. Returns the number of dropped and kept blocks
+fn write_without_family(
+    input: impl Read,
+    output: impl Write + Seek,
+    family_id: u32,
+    magics: Uf2Magics,
+) -> Result<(usize, usize), Box<dyn Error>> {
+    let mut writer = Uf2SeekableWriter::new(output)?;
+    let mut dropped = 0;
+
+    for (block_no, block) in Uf2Reader::new(input).enumerate() {
+        let mut block = block?;
+        if block.magics() != magics {
+            return Err(format!("Block {block_no} has bad magic").into());
         }
-    } else {
-        File::create(Opts::global().output_path())?
-    };
 
-    if let Err(err) = elf2uf2(input, BufWriter::new(output)) {
-        if Opts::global().deploy {
-            fs::remove_file(deployed_path.unwrap())?;
+        if block.family_id() == Some(family_id) {
+            dropped += 1;
         } else {
-            fs::remove_file(Opts::global().output_path())?;
+            writer.write_block(&mut block)?;
         }
-        return Err(err);
     }
 
-    // New line after progress bar
-    println!();
+    if dropped == 0 {
+        return Err(format!("No blocks of family {family_id:#010x}").into());
+    }
+    let kept = writer.blocks();
+    if kept == 0 {
+        return Err(format!("All blocks are of family {family_id:#010x}").into());
+    }
+
+    writer.finish()?.flush()?;
+
+    Ok((dropped, kept))
+}
+
+fn merge(inputs: &[PathBuf], output: &Path) -> Result<(), Box<dyn Error>> {
+    let mut readers = inputs
+        .iter()
+        .map(|input| File::open(input).map(BufReader::new))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // Merged in memory so that inputs that don't merge leave no output behind
+    let mut merged = Vec::new();
+    let blocks = merge_uf2(&mut readers, &mut merged, Opts::global().magics())?;
+    fs::write(output, merged)?;
+
+    println!("Merged {} files into {blocks} blocks", inputs.len());
+
+    Ok(())
+}
+
+fn split(input: &Path) -> Result<(), Box<dyn Error>> {
+    let families =
+        split_uf2_by_family(BufReader::new(File::open(input)?), Opts::global().magics())?;
+
+    for (family, bytes) in families {
+        let family = family.map_or("none".to_string(), |id| format!("{id:08x}"));
+        let output = input.with_extension(format!("{family}.uf2"));
+        fs::write(&output, &bytes)?;
+        println!(
+            "{} blocks to {}",
+            bytes.len() / UF2_BLOCK_SIZE,
+            output.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// The drive to deploy to, the --drive if it is mounted or else the only mounted pico. Several
+/// mounted picos without --drive are an error listing them
+fn pico_drive() -> Result<Option<PathBuf>, Box<dyn Error>> {
+    if let Some(drive) = &Opts::global().drive {
+        return Ok(drive.join("INFO_UF2.TXT").is_file().then(|| drive.clone()));
+    }
+
+    let mut drives = find_pico_drives();
+    if drives.len() > 1 {
+        let list: Vec<_> = drives.iter().map(|drive| drive.to_string_lossy()).collect();
+        return Err(format!(
+            "Found {} mounted picos, choose one with --drive: {}",
+            drives.len(),
+            list.join(", ")
+        )
+        .into());
+    }
+    Ok(drives.pop())
+}
+
+/// Polls for the drive to deploy to every 200ms for up to `seconds`. Returns without error when
+/// none appeared, the deploy then fails as usual
+fn wait_for_pico(seconds: u32) -> Result<(), Box<dyn Error>> {
+    let polls = seconds.saturating_mul(5);
+    let mut counter = 0;
+
+    while pico_drive()?.is_none() && counter < polls {
+        if counter == 0 {
+            println!("Waiting for pico...");
+        }
+        counter += 1;
+
+        std::thread::sleep(std::time::Duration::from_millis(200));
+    }
+
+    Ok(())
+}
+
+/// Converts the input onto the mounted pico. Transient failures are retried, looking up the
+/// drive again in case it re-enumerated
+fn deploy(input: &Path) -> Result<(), Box<dyn Error>> {
+    #[allow(unused_mut)]
+    let mut wait = Opts::global().wait;
 
     #[cfg(feature = "serial")]
-    if Opts::global().serial {
-        use std::process;
-        use std::sync::{Arc, Mutex};
-        use std::time::Duration;
-        use std::{io, thread};
-
-        let mut counter = 0;
-
-        let serial_port_info = 'find_loop: loop {
-            for port in serialport::available_ports()? {
-                if !serial_ports_before.contains(&port) {
-                    println!("Found pico serial on {}", &port.port_name);
-                    break 'find_loop Some(port);
+    if let Some(port_name) = &Opts::global().reboot {
+        serial::reset_to_bootsel(port_name)?;
+        println!("Rebooted {port_name} into BOOTSEL");
+        wait = wait.or(Some(REBOOT_WAIT_SECONDS));
+    }
+
+    if let Some(seconds) = wait {
+        wait_for_pico(seconds)?;
+    }
+
+    let attempts = Opts::global().deploy_attempts.max(1);
+    let mut attempt = 1;
+
+    loop {
+        let err: Box<dyn Error> = match pico_drive()? {
+            Some(pico_drive) => {
+                println!("Found pico uf2 disk {}", &pico_drive.to_string_lossy());
+                let board = deploy_board(&pico_drive)?;
+                match convert_input(input, &pico_drive.join("out.uf2"), &*board) {
+                    Ok(()) => return Ok(()),
+                    // A bad input fails the same way every time, only I/O errors may be transient
+                    Err(err) if err.is::<io::Error>() => {
+                        format!("{}: {err}", input.display()).into()
+                    }
+                    Err(err) => return Err(format!("{}: {err}", input.display()).into()),
                 }
             }
+            None => match &Opts::global().drive {
+                Some(drive) => format!(
+                    "{} is not a mounted pico, it has no INFO_UF2.TXT",
+                    drive.display()
+                )
+                .into(),
+                None => "Unable to find mounted pico".into(),
+            },
+        };
+
+        if attempt >= attempts {
+            return Err(err);
+        }
+        eprintln!("Deploy attempt {attempt} of {attempts} failed: {err}, retrying");
+        std::thread::sleep(std::time::Duration::from_millis(500 * u64::from(attempt)));
+        attempt += 1;
+    }
+}
 
-            counter += 1;
+/// Reboots a board that stayed in its bootloader after deploy, only warns when that fails
+#[cfg(feature = "usb")]
+fn reset_after_deploy() {
+    // Give a board that reboots on its own the time to do so
+    std::thread::sleep(std::time::Duration::from_secs(1));
 
-            if counter == 100 {
-                break None;
+    match usb::reboot_bootloader_devices() {
+        Ok(0) => {
+            if Opts::global().verbosity() >= Verbosity::Info {
+                println!("No board left in bootloader mode to reboot");
             }
+        }
+        Ok(rebooted) => println!("Rebooted {rebooted} board(s)"),
+        Err(err) => eprintln!("Warning: unable to reboot the board: {err}"),
+    }
+}
 
-            thread::sleep(Duration::from_millis(200));
+#[cfg(not(feature = "usb"))]
+fn reset_after_deploy() {
+    eprintln!("Warning: --reset-after needs the usb feature to reboot the board");
+}
+
+fn main() -> ExitCode {
+    OPTS.set(Opts::parse()).unwrap();
+
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err((failure, err)) => {
+            eprintln!("Error: {err}");
+            ExitCode::from(failure as u8)
+        }
+    }
+}
+
+fn run() -> Result<(), (Failure, Box<dyn Error>)> {
+    if let Some(command) = &Opts::global().command {
+        return match command {
+            Command::Boards => {
+                list_boards();
+                Ok(())
+            }
+            Command::ToElf { input, output } => to_elf(input, output).or_fail(Failure::Conversion),
+            Command::Devices => list_devices().or_fail(Failure::Conversion),
+            Command::Check {
+                input,
+                verify_against,
+                family,
+            } => check(input, verify_against.as_deref(), family.as_deref())
+                .or_fail(Failure::Validation),
+            Command::DropFamily {
+                family,
+                input,
+                output,
+            } => drop_family_blocks(family, input, output).or_fail(Failure::Conversion),
+            Command::Split { input } => split(input).or_fail(Failure::Conversion),
+            Command::Merge { inputs, output } => merge(inputs, output).or_fail(Failure::Conversion),
+            Command::Ranges { family } => find_board(family.as_deref())
+                .and_then(|board| print_ranges(&*board))
+                .or_fail(Failure::Conversion),
         };
+    }
 
-        if let Some(serial_port_info) = serial_port_info {
-            for _ in 0..100 {
-                if let Ok(port) = serialport::new(&serial_port_info.port_name, 115200)
-                    .timeout(Duration::from_millis(100))
-                    .flow_control(serialport::FlowControl::None)
-                    .open()
-                {
-                    let port = Arc::new(Mutex::new(port));
-
-                    let handler = {
-                        let port = port.clone();
-                        move || {
-                            let mut port = port.lock().unwrap();
-                            port.write_all(b"elf2uf2-term\n\r").ok();
-                            port.flush().ok();
-                            process::exit(0);
-                        }
-                    };
-
-                    if Opts::global().term {
-                        ctrlc::set_handler(handler.clone()).expect("Error setting Ctrl-C handler");
-                    }
+    #[cfg(feature = "serial")]
+    let mut serial_ports_before = serialport::available_ports().or_fail(Failure::Serial)?;
+    // The port of a rebooted pico comes back under the same name once the image runs
+    #[cfg(feature = "serial")]
+    if let Some(port_name) = &Opts::global().reboot {
+        serial_ports_before.retain(|port| &port.port_name != port_name);
+    }
 
-                    let data_terminal_ready_succeeded = {
-                        let mut port = port.lock().unwrap();
-                        port.write_data_terminal_ready(true).is_ok()
-                    };
-                    if data_terminal_ready_succeeded {
-                        let mut serial_buf = [0; 1024];
-                        loop {
-                            let read = {
-                                let mut port = port.lock().unwrap();
-                                port.read(&mut serial_buf)
-                            };
-
-                            match read {
-                                Ok(t) => {
-                                    io::stdout().write_all(&serial_buf[..t])?;
-                                    io::stdout().flush()?;
-                                }
-                                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => (),
-                                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
-                                    if Opts::global().term {
-                                        handler();
-                                    }
-                                    return Err(e.into());
-                                }
-                                Err(e) => return Err(e.into()),
-                            }
-                        }
-                    }
-                }
+    let conversions = Opts::global().conversions();
+    let to_stdout = !Opts::global().deploy && Opts::global().writes_to_stdout();
+    if to_stdout
+        && (Opts::global().verbose > 0
+            || Opts::global().analyze
+            || Opts::global().dump_entry
+            || Opts::global().summary_only)
+    {
+        return Err("-v, --analyze, --dump-entry and --summary-only print to stdout, which the output is written to")
+            .or_fail(Failure::Conversion);
+    }
+    let failure = match Opts::global().output_format {
+        OutputFormat::Uf2 | OutputFormat::Hex | OutputFormat::Bin => Failure::Conversion,
+        OutputFormat::Elf => Failure::Validation,
+    };
+
+    if Opts::global().deploy {
+        let [(input, _)] = &conversions[..] else {
+            return Err("Deploy requires a single input file").or_fail(Failure::Conversion);
+        };
+
+        deploy(input).or_fail(Failure::Deploy)?;
 
-                thread::sleep(Duration::from_millis(200));
+        if Opts::global().reset_after {
+            reset_after_deploy();
+        }
+    } else if let [(input, output)] = &conversions[..] {
+        let board = Opts::global().board().or_fail(Failure::Conversion)?;
+        convert(input, output, &*board).or_fail(failure)?;
+    } else {
+        let board = Opts::global().board().or_fail(Failure::Conversion)?;
+        let mut failed = 0;
+
+        for (input, output) in &conversions {
+            match convert(input, output, &*board) {
+                Ok(()) => println!("{} -> {}", input.display(), output.display()),
+                Err(err) => {
+                    eprintln!("{err}");
+                    failed += 1;
+                }
             }
         }
+
+        if failed > 0 {
+            return Err(format!(
+                "{failed} of {} conversions failed",
+                conversions.len()
+            ))
+            .or_fail(failure);
+        }
+    }
+
+    // New line after progress bar
+    if !to_stdout {
+        println!();
+    }
+
+    #[cfg(feature = "serial")]
+    if Opts::global().serial {
+        if let Some(port) = serial::find_new_port(&serial_ports_before).or_fail(Failure::Serial)? {
+            println!("Found pico serial on {}", port.port_name);
+            serial::monitor_serial(&port.port_name, serial::PICO_BAUD, Opts::global().term)
+                .or_fail(Failure::Serial)?;
+        }
     }
 
     Ok(())
@@ -363,31 +1632,352 @@ fn main() -> Result<(), Box<dyn Error>> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io;
+    use crate::boards::{Teensy4, RP2350};
+    use std::{
+        env, io,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+    use zerocopy::IntoBytes;
+
+    /// A new empty directory for one test. Tests run in parallel, and in more than one process when
+    /// the features differ, so the name holds the process id and a count
+    fn temp_dir(name: &str) -> PathBuf {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = env::temp_dir().join(format!(
+            "elf2uf2-{name}-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
 
     #[test]
-    pub fn hello_usb() {
-        // Horrendous hack to get it to stop complaining about opts
-        // TODO: just pass opts by reference, or use log crate
-        OPTS.set(Default::default()).ok();
+    pub fn family_dropped_while_streaming() {
+        let riscv = uf2::RP2350_ARM_S_FAMILY_ID + 1;
+        let mut input = Vec::new();
+        for (block_no, family_id) in (0..).zip([uf2::RP2350_ARM_S_FAMILY_ID, riscv, riscv]) {
+            let mut block = Uf2Block::new();
+            block.header.flags = uf2::UF2_FLAG_FAMILY_ID_PRESENT;
+            block.header.file_size = family_id;
+            block.header.block_no = block_no;
+            block.header.num_blocks = 3;
+            input.extend_from_slice(&block.to_bytes());
+        }
+        let without = |input: &[u8], family_id| {
+            let mut output = io::Cursor::new(Vec::new());
+            write_without_family(input, &mut output, family_id, Uf2Magics::default())
+                .map(|counts| (counts, output.into_inner()))
+                .map_err(|err| err.to_string())
+        };
 
-        let bytes_in = io::Cursor::new(&include_bytes!("../hello_usb.elf")[..]);
-        let mut bytes_out = Vec::new();
-        elf2uf2(bytes_in, &mut bytes_out).unwrap();
+        let (counts, riscv_only) = without(&input, uf2::RP2350_ARM_S_FAMILY_ID).unwrap();
+        assert_eq!(counts, (1, 2));
+        let numbers: Vec<_> = Uf2Reader::new(&riscv_only[..])
+            .map(|block| {
+                let block = block.unwrap();
+                (
+                    block.family_id(),
+                    block.header.block_no,
+                    block.header.num_blocks,
+                )
+            })
+            .collect();
+        assert_eq!(numbers, [(Some(riscv), 0, 2), (Some(riscv), 1, 2)]);
+
+        let err = without(&input, riscv + 1).unwrap_err();
+        assert!(err.starts_with("No blocks of family"), "{err}");
+        let err = without(&riscv_only, riscv).unwrap_err();
+        assert!(err.starts_with("All blocks are of family"), "{err}");
+    }
+
+    #[test]
+    pub fn uf2_against_elf() {
+        let elf = &include_bytes!("../hello_usb.elf")[..];
+        let mut uf2 = include_bytes!("../hello_usb.uf2").to_vec();
+        let verify = |uf2: &[u8], board: &dyn BoardInfo| {
+            verify_uf2_against_elf(uf2, io::Cursor::new(elf), board, Uf2Magics::default())
+                .map_err(|err| err.to_string())
+        };
+
+        assert_eq!(verify(&uf2, &RP2040), Ok(()));
+
+        // The second byte of the payload of the second block
+        uf2[512 + 32 + 1] ^= 0xff;
+        let err = verify(&uf2, &RP2040).unwrap_err();
+        assert!(err.starts_with("0x10000101 is"), "{err}");
+        uf2[512 + 32 + 1] ^= 0xff;
+
+        uf2.drain(512..1024);
+        let err = verify(&uf2, &RP2040).unwrap_err();
+        assert!(err.contains("0x10000100 is in no block"), "{err}");
+
+        // No segment is in memory the teensy4 takes contents from, so nothing is compared
+        assert_eq!(verify(&[], &Teensy4), Ok(()));
+    }
 
+    #[test]
+    pub fn raw_binary() {
+        // The flash image of hello_usb, whose blocks are contiguous
+        let image: Vec<u8> = include_bytes!("../hello_usb.uf2")
+            .chunks_exact(512)
+            .flat_map(|block| block[32..32 + 256].to_vec())
+            .collect();
+
+        let mut bytes_out = Vec::new();
+        let elf = binary_elf(&image[..], 0x10000000, &RP2040).unwrap();
+        elf2uf2(
+            elf,
+            &mut bytes_out,
+            &mut NoProgress,
+            &RP2040,
+            &ConversionOptions::default(),
+        )
+        .unwrap();
         assert_eq!(bytes_out, include_bytes!("../hello_usb.uf2"));
+
+        let err = binary_elf(&image[..], 0x30000000, &RP2040).unwrap_err();
+        assert!(err.to_string().contains("0x30000000"), "{err}");
+
+        let err = binary_elf(&[][..], 0x10000000, &RP2040).unwrap_err();
+        assert!(err.to_string().contains("empty"), "{err}");
+
+        let err = binary_elf(&[0; 2][..], u32::MAX, &RP2040).unwrap_err();
+        assert!(err.to_string().contains("past 4 GiB"), "{err}");
+    }
+
+    #[test]
+    pub fn fill_byte_option() {
+        assert_eq!(parse_u8("0xff"), Ok(0xff));
+        assert_eq!(parse_u8("7"), Ok(7));
+        assert!(parse_u8("256").is_err());
     }
 
     #[test]
-    pub fn hello_serial() {
-        // Horrendous hack to get it to stop complaining about opts
-        // TODO: just pass opts by reference, or use log crate
+    pub fn verify_read_back() {
         OPTS.set(Default::default()).ok();
 
-        let bytes_in = io::Cursor::new(&include_bytes!("../hello_serial.elf")[..]);
-        let mut bytes_out = Vec::new();
-        elf2uf2(bytes_in, &mut bytes_out).unwrap();
+        let dir = temp_dir("verify");
+        let path = dir.join("out.uf2");
+
+        let written = include_bytes!("../hello_usb.uf2");
+        fs::write(&path, written).unwrap();
+        assert!(verify_deployed(&path, written).is_ok());
+
+        let mut moved = written.to_vec();
+        let mut block = Uf2Block::from_bytes(moved[3 * 512..4 * 512].try_into().unwrap()).unwrap();
+        block.header.target_addr += 0x100;
+        moved[3 * 512..4 * 512].copy_from_slice(block.as_bytes());
+        fs::write(&path, &moved).unwrap();
+        let err = verify_deployed(&path, written).unwrap_err();
+        assert!(err.to_string().contains("block 3 reads back"), "{err}");
+
+        fs::write(&path, &written[..512]).unwrap();
+        let err = verify_deployed(&path, written).unwrap_err();
+        assert!(err.to_string().contains("read back 1 blocks"), "{err}");
+
+        // The drive unmounting after the bootloader took the image is not a failure
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(verify_deployed(&path, written).is_ok());
+    }
+
+    #[test]
+    pub fn convert_between_paths() {
+        OPTS.set(Default::default()).ok();
+
+        let dir = temp_dir("convert");
+        let output = dir.join("hello_usb.uf2");
+
+        let (written, stats) = elf2uf2_file(
+            Path::new("hello_usb.elf"),
+            &output,
+            &mut NoProgress,
+            &RP2040,
+            &ConversionOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(written, output);
+        assert_eq!(stats.blocks, 89);
+        assert_eq!(
+            fs::read(&output).unwrap(),
+            include_bytes!("../hello_usb.uf2")
+        );
+
+        // A failed conversion leaves neither the output nor the partial file
+        let output = dir.join("readme.uf2");
+        assert!(elf2uf2_file(
+            Path::new("README.md"),
+            &output,
+            &mut NoProgress,
+            &RP2040,
+            &ConversionOptions::default()
+        )
+        .is_err());
+        assert!(!output.exists());
+        assert!(!dir.join("readme.uf2.partial").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    pub fn free_space_check() {
+        assert!(check_free_space(Path::new("hello_usb.uf2"), 0).is_ok());
+
+        // Only fails where the free space is known
+        if let Err(err) = check_free_space(Path::new("hello_usb.uf2"), usize::MAX) {
+            assert!(err.to_string().contains("Not enough space"), "{err}");
+        }
+    }
+
+    #[test]
+    pub fn board_by_family() {
+        assert_eq!(find_board(None).unwrap().name(), "rp2040");
+        assert_eq!(find_board(Some("Teensy4")).unwrap().name(), "teensy4");
+        assert_eq!(find_board(Some("0xe48bff59")).unwrap().name(), "rp2350");
+        assert_eq!(find_board(Some("pico-w")).unwrap().name(), "rp2040");
+        assert_eq!(find_board(Some("Pico2")).unwrap().name(), "rp2350");
+        assert!(find_board(Some("rp9999")).is_err());
+    }
 
-        assert_eq!(bytes_out, include_bytes!("../hello_serial.uf2"));
+    #[test]
+    pub fn board_of_drive() {
+        let dir = temp_dir("drive");
+        let board_of = |info: &str| {
+            fs::write(dir.join("INFO_UF2.TXT"), info).unwrap();
+            drive_board(&dir).map(|board| board.name().to_string())
+        };
+
+        assert_eq!(
+            board_of("UF2 Bootloader v3.0\r\nModel: Raspberry Pi RP2\r\nBoard-ID: RPI-RP2\r\n")
+                .unwrap(),
+            "rp2040"
+        );
+        assert_eq!(
+            board_of("UF2 Bootloader v1.0\nModel: Raspberry Pi RP2350\nBoard-ID: RP2350\n")
+                .unwrap(),
+            "rp2350"
+        );
+        assert!(board_of("Board-ID: SAMD21\n").is_err());
+        assert!(board_of("UF2 Bootloader\n").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    pub fn drive_info() {
+        let info = Uf2DriveInfo::parse(
+            "UF2 Bootloader v3.0\r\nModel: Raspberry Pi RP2\r\nBoard-ID: RPI-RP2\r\n",
+        );
+        assert_eq!(
+            info,
+            Uf2DriveInfo {
+                bootloader: "UF2 Bootloader v3.0".to_owned(),
+                model: Some("Raspberry Pi RP2".to_owned()),
+                board_id: Some("RPI-RP2".to_owned()),
+            }
+        );
+        assert!(info.check_board(&RP2040).is_ok());
+        let err = info.check_board(&RP2350).unwrap_err();
+        assert!(
+            err.contains("Raspberry Pi RP2 (rp2040), not a rp2350"),
+            "{err}"
+        );
+
+        // Nothing to check against without a known Board-ID
+        let info = Uf2DriveInfo::parse("UF2 Bootloader v1.0\nModel: Something\n");
+        assert_eq!(info.board_id, None);
+        assert!(info.check_board(&RP2350).is_ok());
+    }
+
+    #[test]
+    pub fn family_id_option() {
+        let family_id = |value: &str| {
+            Opts::try_parse_from(["elf2uf2-rs", "--family-id", value, "a.elf"])
+                .map(|opts| opts.family_id)
+        };
+
+        assert_eq!(family_id("0x00ff6919").unwrap(), Some(0x00ff6919));
+        assert_eq!(family_id("3735928559").unwrap(), Some(0xdeadbeef));
+        let err = family_id("0x100000000").unwrap_err();
+        assert!(err.to_string().contains("not a 32 bit number"), "{err}");
+        assert!(family_id("rp2040").is_err());
+    }
+
+    #[test]
+    pub fn address_range_option() {
+        assert_eq!(
+            parse_address_range("0x10000000-0x10001000"),
+            Ok(0x10000000..0x10001000)
+        );
+        assert_eq!(
+            parse_address_range("0x10000000:0x10001000"),
+            Ok(0x10000000..0x10001000)
+        );
+        assert_eq!(parse_address_range(" 16 : 32 "), Ok(16..32));
+        assert!(parse_address_range("0x10000000").is_err());
+        assert!(parse_address_range("0x20:0x10").is_err());
+    }
+
+    #[test]
+    pub fn output_paths() {
+        let conversions = |files: &[&str]| {
+            Opts {
+                files: files.iter().map(|file| file.to_string()).collect(),
+                ..Default::default()
+            }
+            .conversions()
+        };
+        let pair = |input: &str, output: &str| (PathBuf::from(input), PathBuf::from(output));
+
+        assert_eq!(
+            conversions(&["firmware.elf"]),
+            [pair("firmware.elf", "firmware.uf2")]
+        );
+        assert_eq!(
+            conversions(&["firmware.elf", "out"]),
+            [pair("firmware.elf", "out.uf2")]
+        );
+        assert_eq!(
+            conversions(&["firmware.elf", "out.bin"]),
+            [pair("firmware.elf", "out.bin")]
+        );
+        assert_eq!(
+            conversions(&["firmware.elf", "-"]),
+            [pair("firmware.elf", "-")]
+        );
+        assert_eq!(
+            conversions(&["a.elf", "b.elf", "c"]),
+            [
+                pair("a.elf", "a.uf2"),
+                pair("b.elf", "b.uf2"),
+                pair("c", "c.uf2")
+            ]
+        );
+
+        let opts = Opts {
+            files: vec!["a.elf".into(), "out".into()],
+            output_dir: Some("build".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            opts.conversions(),
+            [pair("a.elf", "build/a.uf2"), pair("out", "build/out.uf2")]
+        );
+
+        let opts = Opts {
+            files: vec!["a.elf".into(), "b.elf".into()],
+            output_format: OutputFormat::Elf,
+            ..Default::default()
+        };
+        assert_eq!(opts.conversions(), [pair("a.elf", "b.elf")]);
+
+        let opts = Opts {
+            files: vec!["a.elf".into()],
+            output_format: OutputFormat::Elf,
+            ..Default::default()
+        };
+        assert_eq!(opts.conversions(), [pair("a.elf", "a.checked.elf")]);
     }
 }
@@ -0,0 +1,96 @@
+//! The blocks RP2350 images carry metadata in, see section 5.9 of the RP2350 datasheet
+
+/// The bootrom only looks for the first block in this many bytes at the start of an image
+pub const IMAGE_DEF_SEARCH_SIZE: usize = 4096;
+
+const BLOCK_MARKER_START: u32 = 0xffffded3;
+const BLOCK_MARKER_END: u32 = 0xab123579;
+
+/// Items with bit 7 of their type set have a 16 bit size
+const ITEM_TWO_BYTE_SIZE: u8 = 0x80;
+const ITEM_IMAGE_TYPE: u8 = 0x42;
+const ITEM_LAST: u8 = 0xff;
+const IMAGE_TYPE_EXE: u8 = 0x1;
+
+/// Offset of the first well formed block in `image` with an IMAGE_DEF for an executable
+pub fn find_image_def(image: &[u8]) -> Option<usize> {
+    let image = &image[..image.len().min(IMAGE_DEF_SEARCH_SIZE)];
+    let word = |offset: usize| {
+        image
+            .get(offset..offset + 4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+    };
+
+    (0..image.len())
+        .step_by(4)
+        .filter(|&offset| word(offset) == Some(BLOCK_MARKER_START))
+        .find(|&offset| {
+            let mut item = offset + 4;
+            let mut is_exe = false;
+
+            loop {
+                let Some(&[typ, byte1, byte2, _]) = image.get(item..item + 4) else {
+                    return false;
+                };
+                if typ == ITEM_LAST {
+                    // Followed by the link to the next block and the end marker
+                    return is_exe && word(item + 8) == Some(BLOCK_MARKER_END);
+                }
+
+                // Sizes are in words, the image type flags follow the size of its 1 byte
+                let size = if typ & ITEM_TWO_BYTE_SIZE != 0 {
+                    usize::from(u16::from_le_bytes([byte1, byte2]))
+                } else {
+                    usize::from(byte1)
+                };
+                if size == 0 {
+                    return false;
+                }
+                if typ == ITEM_IMAGE_TYPE && byte2 & 0xf == IMAGE_TYPE_EXE {
+                    is_exe = true;
+                }
+
+                item += 4 * size;
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What the pico SDK embeds in Arm secure executables
+    const IMAGE_DEF: [u8; 20] = [
+        0xd3, 0xde, 0xff, 0xff, // start marker
+        0x42, 0x01, 0x21, 0x10, // image type: executable, Arm, secure, RP2350
+        0xff, 0x01, 0x00, 0x00, // last item, 1 word of items before it
+        0x00, 0x00, 0x00, 0x00, // link to itself
+        0x79, 0x35, 0x12, 0xab, // end marker
+    ];
+
+    #[test]
+    pub fn image_def() {
+        let mut image = vec![0; IMAGE_DEF_SEARCH_SIZE];
+        assert_eq!(find_image_def(&image), None);
+
+        image[0x100..0x100 + IMAGE_DEF.len()].copy_from_slice(&IMAGE_DEF);
+        assert_eq!(find_image_def(&image), Some(0x100));
+
+        // A data image does not boot
+        image[0x106] = 0x22;
+        assert_eq!(find_image_def(&image), None);
+        image[0x106] = 0x21;
+
+        let mut broken = image.clone();
+        broken[0x110] = 0;
+        assert_eq!(find_image_def(&broken), None);
+
+        // Past where the bootrom looks
+        let mut image = vec![0; 2 * IMAGE_DEF_SEARCH_SIZE];
+        image[IMAGE_DEF_SEARCH_SIZE..][..IMAGE_DEF.len()].copy_from_slice(&IMAGE_DEF);
+        assert_eq!(find_image_def(&image), None);
+
+        let uf2 = include_bytes!("../hello_usb.uf2");
+        assert_eq!(find_image_def(&uf2[32..32 + 256]), None);
+    }
+}
@@ -0,0 +1,155 @@
+use pbr::{ProgressBar, Units};
+use std::{
+    io::Stdout,
+    time::{Duration, Instant},
+};
+
+pub trait ProgressReporter {
+    fn start(&mut self, total_bytes: usize);
+    fn advance(&mut self, bytes: usize);
+    fn finish(&mut self);
+
+    /// Called before every `advance` with the time since `start` and an estimate of the time
+    /// left
+    fn update_eta(&mut self, _elapsed: Duration, _remaining: Duration) {}
+}
+
+/// Estimates the time a conversion has left from the bytes written so far
+pub struct Eta {
+    started: Instant,
+    total_bytes: usize,
+    done_bytes: usize,
+}
+
+impl Eta {
+    pub fn new(total_bytes: usize) -> Self {
+        Self {
+            started: Instant::now(),
+            total_bytes,
+            done_bytes: 0,
+        }
+    }
+
+    /// Advances `reporter` by `bytes`, updating its estimate first
+    pub fn advance(&mut self, reporter: &mut dyn ProgressReporter, bytes: usize) {
+        self.done_bytes += bytes;
+        let elapsed = self.started.elapsed();
+        reporter.update_eta(elapsed, self.remaining(elapsed));
+        reporter.advance(bytes);
+    }
+
+    /// Time left if the rest is written at the average rate so far
+    fn remaining(&self, elapsed: Duration) -> Duration {
+        if self.done_bytes == 0 {
+            return Duration::ZERO;
+        }
+        let left = self.total_bytes.saturating_sub(self.done_bytes);
+        elapsed.mul_f64(left as f64 / self.done_bytes as f64)
+    }
+}
+
+/// Reports nothing
+#[derive(Default)]
+pub struct NoProgress;
+
+impl ProgressReporter for NoProgress {
+    fn start(&mut self, _total_bytes: usize) {}
+    fn advance(&mut self, _bytes: usize) {}
+    fn finish(&mut self) {}
+}
+
+/// Draws a progress bar on stdout
+#[derive(Default)]
+pub struct ProgressBarReporter {
+    pb: Option<ProgressBar<Stdout>>,
+}
+
+impl ProgressReporter for ProgressBarReporter {
+    fn start(&mut self, total_bytes: usize) {
+        let mut pb = ProgressBar::new(total_bytes as u64);
+        pb.set_units(Units::Bytes);
+        // Replaced by the estimate from `update_eta`
+        pb.show_time_left = false;
+        self.pb = Some(pb);
+    }
+
+    fn advance(&mut self, bytes: usize) {
+        if let Some(pb) = &mut self.pb {
+            pb.add(bytes as u64);
+        }
+    }
+
+    fn update_eta(&mut self, _elapsed: Duration, remaining: Duration) {
+        if let Some(pb) = &mut self.pb {
+            pb.message(&format!("ETA {}s ", remaining.as_secs()));
+        }
+    }
+
+    fn finish(&mut self) {
+        self.pb = None;
+    }
+}
+
+/// Reports through closures, for callers that don't want to implement `ProgressReporter`
+pub struct FnProgress<S, A, F> {
+    start: S,
+    advance: A,
+    finish: F,
+}
+
+impl<S, A, F> FnProgress<S, A, F>
+where
+    S: FnMut(usize),
+    A: FnMut(usize),
+    F: FnMut(),
+{
+    pub fn new(start: S, advance: A, finish: F) -> Self {
+        Self {
+            start,
+            advance,
+            finish,
+        }
+    }
+}
+
+impl<S, A, F> ProgressReporter for FnProgress<S, A, F>
+where
+    S: FnMut(usize),
+    A: FnMut(usize),
+    F: FnMut(),
+{
+    fn start(&mut self, total_bytes: usize) {
+        (self.start)(total_bytes)
+    }
+
+    fn advance(&mut self, bytes: usize) {
+        (self.advance)(bytes)
+    }
+
+    fn finish(&mut self) {
+        (self.finish)()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn eta_from_average_rate() {
+        let mut eta = Eta::new(4096);
+        assert_eq!(eta.remaining(Duration::from_secs(1)), Duration::ZERO);
+
+        let mut updates = Vec::new();
+        let mut reporter = FnProgress::new(|_| {}, |bytes| updates.push(bytes), || {});
+        eta.advance(&mut reporter, 1024);
+        assert_eq!(updates, [1024]);
+
+        assert_eq!(
+            eta.remaining(Duration::from_secs(2)),
+            Duration::from_secs(6)
+        );
+        eta.advance(&mut NoProgress, 3072);
+        assert_eq!(eta.remaining(Duration::from_secs(2)), Duration::ZERO);
+    }
+}
@@ -0,0 +1,117 @@
+use serialport::SerialPortInfo;
+use std::{
+    error::Error,
+    io::{self, Write},
+    process,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Baud rate of the serial port of a running pico
+pub const PICO_BAUD: u32 = 115200;
+
+/// Waits up to 20 seconds for a serial port that is not in `ports_before` to appear
+pub fn find_new_port(
+    ports_before: &[SerialPortInfo],
+) -> Result<Option<SerialPortInfo>, serialport::Error> {
+    let mut counter = 0;
+
+    loop {
+        for port in serialport::available_ports()? {
+            if !ports_before.contains(&port) {
+                return Ok(Some(port));
+            }
+        }
+
+        counter += 1;
+
+        if counter == 100 {
+            return Ok(None);
+        }
+
+        thread::sleep(Duration::from_millis(200));
+    }
+}
+
+/// Prints what arrives on the serial port `port_name` until Ctrl-C. With `term` the device is
+/// sent a termination message on Ctrl-C, and the process exits successfully. Opening the port is
+/// retried for up to 20 seconds, when it never opens this returns without error
+pub fn monitor_serial(port_name: &str, baud: u32, term: bool) -> io::Result<()> {
+    for _ in 0..100 {
+        if let Ok(port) = serialport::new(port_name, baud)
+            .timeout(Duration::from_millis(100))
+            .flow_control(serialport::FlowControl::None)
+            .open()
+        {
+            let port = Arc::new(Mutex::new(port));
+
+            let send_term = {
+                let port = port.clone();
+                move || {
+                    let mut port = port.lock().unwrap();
+                    port.write_all(b"elf2uf2-term\n\r").ok();
+                    port.flush().ok();
+                }
+            };
+
+            // Ctrl-C is how the monitoring is meant to end, so it exits successfully
+            if term {
+                let send_term = send_term.clone();
+                ctrlc::set_handler(move || {
+                    send_term();
+                    process::exit(0);
+                })
+                .expect("Error setting Ctrl-C handler");
+            }
+
+            let data_terminal_ready_succeeded = {
+                let mut port = port.lock().unwrap();
+                port.write_data_terminal_ready(true).is_ok()
+            };
+            if data_terminal_ready_succeeded {
+                let mut serial_buf = [0; 1024];
+                loop {
+                    let read = {
+                        let mut port = port.lock().unwrap();
+                        port.read(&mut serial_buf)
+                    };
+
+                    match read {
+                        Ok(t) => {
+                            io::stdout().write_all(&serial_buf[..t])?;
+                            io::stdout().flush()?;
+                        }
+                        Err(ref e) if e.kind() == io::ErrorKind::TimedOut => (),
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                            if term {
+                                send_term();
+                            }
+                            return Err(e);
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+
+        thread::sleep(Duration::from_millis(200));
+    }
+
+    Ok(())
+}
+
+/// Reboots a running pico into BOOTSEL. Opening its USB serial port at 1200 baud is what the
+/// pico SDK takes as the request to do so
+pub fn reset_to_bootsel(port_name: &str) -> Result<(), Box<dyn Error>> {
+    let mut port = serialport::new(port_name, 1200)
+        .timeout(Duration::from_millis(100))
+        .open()
+        .map_err(|err| format!("Unable to open {port_name} to reboot the pico: {err}"))?;
+
+    // The pico may already be gone by now, so these are allowed to fail
+    port.write_data_terminal_ready(true).ok();
+    port.write_data_terminal_ready(false).ok();
+
+    Ok(())
+}
@@ -0,0 +1,222 @@
+//! Minimal ELF files built in memory, for tests of inputs there are no fixtures for.
+//!
+//! Only hello_usb.elf and hello_serial.elf are checked in, flash images for the RP2040 with their
+//! expected UF2 next to them. RAM binaries and other boards are built with `ElfBuilder` in the
+//! tests themselves, or moved from hello_usb.elf, so they regenerate on every run. Boards that
+//! differ from the RP2040 in one way are a `TestBoard`
+
+use crate::{
+    address_range::{AddressRange, RP2040_ADDRESS_RANGES_FLASH, RP2040_ADDRESS_RANGES_RAM},
+    boards::{AddressLocations, BoardInfo, RP2040},
+    elf::{
+        Elf32Header, Elf32PhEntry, Elf64Header, Elf64PhEntry, ELFCLASS32, ELFCLASS64, ELFDATA2MSB,
+        ELF_MAGIC, EM_ARM, ET_EXEC, PAGE_SIZE, PT_LOAD,
+    },
+};
+use assert_into::AssertInto;
+use std::{io, mem};
+use zerocopy::IntoBytes;
+
+pub const EM_RISCV: u16 = 243;
+
+pub struct ElfBuilder {
+    header: Elf32Header,
+    segments: Vec<(Elf32PhEntry, Vec<u8>)>,
+}
+
+impl ElfBuilder {
+    /// An Arm executable with no segments
+    pub fn new(entry: u32) -> Self {
+        let mut header = Elf32Header::default();
+        header.common.magic = ELF_MAGIC;
+        header.common.arch_class = ELFCLASS32;
+        header.common.endianness = 1;
+        header.common.version = 1;
+        header.common.version2 = 1;
+        header.common.typ = ET_EXEC;
+        header.common.machine = EM_ARM;
+        header.entry = entry;
+        header.eh_size = mem::size_of::<Elf32Header>().assert_into();
+        header.ph_entry_size = mem::size_of::<Elf32PhEntry>().assert_into();
+
+        Self {
+            header,
+            segments: Vec::new(),
+        }
+    }
+
+    pub fn elf_type(mut self, typ: u16) -> Self {
+        self.header.common.typ = typ;
+        self
+    }
+
+    /// Writes the headers big endian, segment contents are written as given
+    pub fn big_endian(mut self) -> Self {
+        self.header.common.endianness = ELFDATA2MSB;
+        self
+    }
+
+    /// Writes the headers as ELF64, with the same values widened to 64 bits
+    pub fn elf64(mut self) -> Self {
+        self.header.common.arch_class = ELFCLASS64;
+        self.header.eh_size = mem::size_of::<Elf64Header>().assert_into();
+        self.header.ph_entry_size = mem::size_of::<Elf64PhEntry>().assert_into();
+        self
+    }
+
+    pub fn machine(mut self, machine: u16) -> Self {
+        self.header.common.machine = machine;
+        self
+    }
+
+    /// Direct access to the header, for inputs the other methods can't describe
+    pub fn header(mut self, f: impl FnOnce(&mut Elf32Header)) -> Self {
+        f(&mut self.header);
+        self
+    }
+
+    /// A loadable segment with `data` at `addr`, both physical and virtual
+    pub fn segment(self, addr: u32, data: &[u8]) -> Self {
+        self.program_header(
+            Elf32PhEntry {
+                typ: PT_LOAD,
+                vaddr: addr,
+                paddr: addr,
+                filez: data.len().assert_into(),
+                memsz: data.len().assert_into(),
+                align: 4,
+                ..Default::default()
+            },
+            data,
+        )
+    }
+
+    /// Any program header. Its file offset is set to where `data` ends up
+    pub fn program_header(mut self, entry: Elf32PhEntry, data: &[u8]) -> Self {
+        self.segments.push((entry, data.to_vec()));
+        self
+    }
+
+    pub fn build(mut self) -> io::Cursor<Vec<u8>> {
+        let elf64 = self.header.common.arch_class == ELFCLASS64;
+        let header_size = if elf64 {
+            mem::size_of::<Elf64Header>()
+        } else {
+            mem::size_of::<Elf32Header>()
+        };
+        let headers_size =
+            header_size + self.segments.len() * usize::from(self.header.ph_entry_size);
+
+        self.header.ph_offset = header_size.assert_into();
+        self.header.ph_num = self.segments.len().assert_into();
+
+        let big_endian = self.header.common.endianness == ELFDATA2MSB;
+        let mut elf = if elf64 {
+            let eh = self.header;
+            let mut eh = Elf64Header {
+                common: eh.common,
+                entry: eh.entry.into(),
+                ph_offset: eh.ph_offset.into(),
+                sh_offset: eh.sh_offset.into(),
+                flags: eh.flags,
+                eh_size: eh.eh_size,
+                ph_entry_size: eh.ph_entry_size,
+                ph_num: eh.ph_num,
+                sh_entry_size: eh.sh_entry_size,
+                sh_num: eh.sh_num,
+                sh_str_index: eh.sh_str_index,
+            };
+            if big_endian {
+                eh.swap_bytes();
+            }
+            eh.as_mut_bytes().to_vec()
+        } else {
+            if big_endian {
+                self.header.swap_bytes();
+            }
+            self.header.as_mut_bytes().to_vec()
+        };
+
+        let mut offset = headers_size;
+        for (entry, data) in &mut self.segments {
+            entry.offset = offset.assert_into();
+            offset += data.len();
+            if elf64 {
+                let mut entry = Elf64PhEntry {
+                    typ: entry.typ,
+                    flags: entry.flags,
+                    offset: entry.offset.into(),
+                    vaddr: entry.vaddr.into(),
+                    paddr: entry.paddr.into(),
+                    filez: entry.filez.into(),
+                    memsz: entry.memsz.into(),
+                    align: entry.align.into(),
+                };
+                if big_endian {
+                    entry.swap_bytes();
+                }
+                elf.extend_from_slice(entry.as_mut_bytes());
+            } else {
+                let mut entry = *entry;
+                if big_endian {
+                    entry.swap_bytes();
+                }
+                elf.extend_from_slice(entry.as_mut_bytes());
+            }
+        }
+        for (_, data) in &self.segments {
+            elf.extend_from_slice(data);
+        }
+
+        io::Cursor::new(elf)
+    }
+}
+
+/// A board the tests configure field by field. The default is the RP2040
+pub struct TestBoard {
+    pub name: &'static str,
+    pub family_id: u32,
+    pub page_size: u32,
+    pub flash_ranges: &'static [AddressRange],
+    pub ram_ranges: &'static [AddressRange],
+    pub locations: AddressLocations,
+}
+
+impl Default for TestBoard {
+    fn default() -> Self {
+        Self {
+            name: "test",
+            family_id: RP2040.family_id(),
+            page_size: PAGE_SIZE,
+            flash_ranges: RP2040_ADDRESS_RANGES_FLASH,
+            ram_ranges: RP2040_ADDRESS_RANGES_RAM,
+            locations: RP2040.address_locations(),
+        }
+    }
+}
+
+impl BoardInfo for TestBoard {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn family_id(&self) -> u32 {
+        self.family_id
+    }
+
+    fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    fn address_ranges_flash(&self) -> &[AddressRange] {
+        self.flash_ranges
+    }
+
+    fn address_ranges_ram(&self) -> &[AddressRange] {
+        self.ram_ranges
+    }
+
+    fn address_locations(&self) -> AddressLocations {
+        self.locations
+    }
+}
@@ -1,7 +1,15 @@
 #![allow(dead_code)]
 
+use crate::md5::md5;
+use assert_into::AssertInto;
 use static_assertions::const_assert;
-use std::mem;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    io::{self, Read, Seek, SeekFrom, Write},
+    mem,
+    ops::Range,
+};
 use zerocopy::{FromBytes, Immutable, IntoBytes};
 
 pub const UF2_MAGIC_START0: u32 = 0x0A324655;
@@ -14,9 +22,15 @@ pub const UF2_FLAG_FAMILY_ID_PRESENT: u32 = 0x00002000;
 pub const UF2_FLAG_MD5_PRESENT: u32 = 0x00004000;
 
 pub const RP2040_FAMILY_ID: u32 = 0xe48bff56;
+pub const RP2350_ARM_S_FAMILY_ID: u32 = 0xe48bff59;
 
-#[repr(packed)]
-#[derive(IntoBytes, FromBytes, Immutable)]
+pub const UF2_BLOCK_SIZE: usize = 512;
+
+/// Bytes at the end of the data region holding the MD5 of a block flagged with it
+pub const UF2_MD5_TRAILER_SIZE: usize = 24;
+
+#[repr(C, packed)]
+#[derive(IntoBytes, FromBytes, Immutable, Copy, Clone)]
 pub struct Uf2BlockHeader {
     pub magic_start0: u32,
     pub magic_start1: u32,
@@ -30,17 +44,807 @@ pub struct Uf2BlockHeader {
 
 pub type Uf2BlockData = [u8; 476];
 
-#[repr(packed)]
-#[derive(IntoBytes, FromBytes, Immutable)]
+#[repr(C, packed)]
+#[derive(IntoBytes, FromBytes, Immutable, Copy, Clone)]
 pub struct Uf2BlockFooter {
     pub magic_end: u32,
 }
 
+/// The magic numbers framing every block. Some forks of UF2 use their own
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Uf2Magics {
+    pub start0: u32,
+    pub start1: u32,
+    pub end: u32,
+}
+
+impl Default for Uf2Magics {
+    fn default() -> Self {
+        Self {
+            start0: UF2_MAGIC_START0,
+            start1: UF2_MAGIC_START1,
+            end: UF2_MAGIC_END,
+        }
+    }
+}
+
+#[repr(C, packed)]
+#[derive(IntoBytes, FromBytes, Immutable)]
+pub struct Uf2Block {
+    pub header: Uf2BlockHeader,
+    pub data: Uf2BlockData,
+    pub footer: Uf2BlockFooter,
+}
+
+impl Uf2Block {
+    /// A block with the standard magics and everything else zeroed
+    pub fn new() -> Self {
+        Self::with_magics(Uf2Magics::default())
+    }
+
+    /// A block with the given magics and everything else zeroed
+    pub fn with_magics(magics: Uf2Magics) -> Self {
+        Self {
+            header: Uf2BlockHeader {
+                magic_start0: magics.start0,
+                magic_start1: magics.start1,
+                flags: 0,
+                target_addr: 0,
+                payload_size: 0,
+                block_no: 0,
+                num_blocks: 0,
+                file_size: 0,
+            },
+            data: [0; 476],
+            footer: Uf2BlockFooter {
+                magic_end: magics.end,
+            },
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; UF2_BLOCK_SIZE] {
+        let mut bytes = [0; UF2_BLOCK_SIZE];
+        bytes.copy_from_slice(self.as_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; UF2_BLOCK_SIZE]) -> Result<Self, Box<dyn Error>> {
+        let block = Self::read_from_bytes(bytes).map_err(|_| "Invalid UF2 block size")?;
+
+        if !block.has_valid_magic() {
+            return Err("Invalid UF2 block magic".into());
+        }
+
+        Ok(block)
+    }
+
+    pub fn magics(&self) -> Uf2Magics {
+        Uf2Magics {
+            start0: self.header.magic_start0,
+            start1: self.header.magic_start1,
+            end: self.footer.magic_end,
+        }
+    }
+
+    /// True if the block has the standard magics
+    pub fn has_valid_magic(&self) -> bool {
+        self.magics() == Uf2Magics::default()
+    }
+
+    /// The family id, if the block carries one
+    pub fn family_id(&self) -> Option<u32> {
+        if self.header.flags & UF2_FLAG_FAMILY_ID_PRESENT != 0 {
+            Some(self.header.file_size)
+        } else {
+            None
+        }
+    }
+
+    /// Stores the address, length and MD5 of the payload in the last 24 bytes of the data region
+    /// and flags the block as carrying them. The payload must end before them
+    pub fn set_md5(&mut self) {
+        let payload_size: usize = self.header.payload_size.assert_into();
+        let trailer_start = self.data.len() - UF2_MD5_TRAILER_SIZE;
+        assert!(payload_size <= trailer_start);
+
+        let digest = md5(&self.data[..payload_size]);
+        let trailer = &mut self.data[trailer_start..];
+        trailer[..4].copy_from_slice(&{ self.header.target_addr }.to_le_bytes());
+        trailer[4..8].copy_from_slice(&{ self.header.payload_size }.to_le_bytes());
+        trailer[8..].copy_from_slice(&digest);
+
+        self.header.flags |= UF2_FLAG_MD5_PRESENT;
+    }
+
+    /// The address, length and MD5 of the region the block carries a checksum of, if any
+    pub fn md5(&self) -> Option<(u32, u32, [u8; 16])> {
+        if self.header.flags & UF2_FLAG_MD5_PRESENT == 0 {
+            return None;
+        }
+
+        let trailer = &self.data[self.data.len() - UF2_MD5_TRAILER_SIZE..];
+        Some((
+            u32::from_le_bytes(trailer[..4].try_into().unwrap()),
+            u32::from_le_bytes(trailer[4..8].try_into().unwrap()),
+            trailer[8..].try_into().unwrap(),
+        ))
+    }
+}
+
+impl Default for Uf2Block {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 const_assert!(mem::size_of::<Uf2BlockHeader>() == 32);
 const_assert!(mem::size_of::<Uf2BlockFooter>() == 4);
 const_assert!(
     mem::size_of::<Uf2BlockHeader>()
         + mem::size_of::<Uf2BlockData>()
         + mem::size_of::<Uf2BlockFooter>()
-        == 512
+        == UF2_BLOCK_SIZE
 );
+const_assert!(mem::size_of::<Uf2Block>() == UF2_BLOCK_SIZE);
+
+/// Reads the blocks of a UF2 stream without validating them
+pub struct Uf2Reader<R> {
+    input: R,
+}
+
+impl<R: Read> Uf2Reader<R> {
+    pub fn new(input: R) -> Self {
+        Self { input }
+    }
+}
+
+impl<R: Read> Iterator for Uf2Reader<R> {
+    type Item = io::Result<Uf2Block>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut bytes = [0; UF2_BLOCK_SIZE];
+        let mut read = 0;
+
+        while read < UF2_BLOCK_SIZE {
+            match self.input.read(&mut bytes[read..]) {
+                Ok(0) if read == 0 => return None,
+                Ok(0) => {
+                    return Some(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "UF2 file ends with a partial block",
+                    )))
+                }
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        Some(Ok(Uf2Block::read_from_bytes(&bytes).unwrap()))
+    }
+}
+
+/// Writes blocks when their count is not known up front. Blocks are numbered within their own
+/// family and written with a placeholder count, that `finish` seeks back to patch
+pub struct Uf2SeekableWriter<W> {
+    output: W,
+    start: u64,
+    families: Vec<Option<u32>>,
+    counts: BTreeMap<Option<u32>, u32>,
+}
+
+impl<W: Write + Seek> Uf2SeekableWriter<W> {
+    pub fn new(mut output: W) -> io::Result<Self> {
+        let start = output.stream_position()?;
+        Ok(Self {
+            output,
+            start,
+            families: Vec::new(),
+            counts: BTreeMap::new(),
+        })
+    }
+
+    /// Writes the block numbered after the previous ones of its family
+    pub fn write_block(&mut self, block: &mut Uf2Block) -> io::Result<()> {
+        let family = block.family_id();
+        let count = self.counts.entry(family).or_default();
+        block.header.block_no = *count;
+        block.header.num_blocks = 0;
+
+        self.output.write_all(&block.to_bytes())?;
+        *count += 1;
+        self.families.push(family);
+        Ok(())
+    }
+
+    pub fn blocks(&self) -> usize {
+        self.families.len()
+    }
+
+    /// Patches the count of its family into every written block and returns the output,
+    /// positioned after the last block
+    pub fn finish(mut self) -> io::Result<W> {
+        let count_offset: u64 = mem::offset_of!(Uf2BlockHeader, num_blocks).assert_into();
+        let block_size: u64 = UF2_BLOCK_SIZE.assert_into();
+
+        for (block_no, family) in (0..).zip(&self.families) {
+            self.output.seek(SeekFrom::Start(
+                self.start + block_no * block_size + count_offset,
+            ))?;
+            self.output.write_all(&self.counts[family].to_le_bytes())?;
+        }
+
+        let blocks: u64 = self.families.len().assert_into();
+        self.output
+            .seek(SeekFrom::Start(self.start + blocks * block_size))?;
+
+        Ok(self.output)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Uf2FamilySummary {
+    pub blocks: usize,
+    pub num_blocks: u32,
+    pub payload_bytes: usize,
+}
+
+/// The header fields of a block that vary between blocks
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Uf2BlockSummary {
+    pub block_no: u32,
+    pub target_addr: u32,
+    pub payload_size: u32,
+    pub flags: u32,
+}
+
+/// Everything found wrong with a UF2 file, blocks are grouped by family id
+#[derive(Debug, Default)]
+pub struct Uf2ValidationReport {
+    pub blocks: usize,
+    pub families: BTreeMap<Option<u32>, Uf2FamilySummary>,
+    /// Every block with valid magics, in file order
+    pub block_summaries: Vec<Uf2BlockSummary>,
+    /// Addresses covered by the payloads of the blocks with valid magics
+    pub address_range: Option<Range<u32>>,
+    /// Some family has its blocks out of block number order. Bootloaders accept that, but it is
+    /// unusual
+    pub out_of_order: bool,
+    pub problems: Vec<String>,
+}
+
+impl Uf2ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Checks every block of a UF2 file and collects all problems found instead of stopping at the first
+pub fn validate_uf2(
+    input: impl Read,
+    magics: Uf2Magics,
+) -> Result<Uf2ValidationReport, Box<dyn Error>> {
+    let mut report = Uf2ValidationReport::default();
+    let mut block_nos = BTreeMap::<Option<u32>, BTreeSet<u32>>::new();
+
+    for (index, block) in Uf2Reader::new(input).enumerate() {
+        let block = block?;
+        let header = block.header;
+        report.blocks += 1;
+
+        if block.magics() != magics {
+            report
+                .problems
+                .push(format!("Block {index}: invalid magic"));
+            continue;
+        }
+
+        let payload_size = header.payload_size;
+        let block_no = header.block_no;
+        let num_blocks = header.num_blocks;
+        let target_addr = header.target_addr;
+
+        report.block_summaries.push(Uf2BlockSummary {
+            block_no,
+            target_addr,
+            payload_size,
+            flags: header.flags,
+        });
+
+        let end = target_addr.saturating_add(payload_size.min(476));
+        report.address_range = Some(match report.address_range.take() {
+            Some(range) => range.start.min(target_addr)..range.end.max(end),
+            None => target_addr..end,
+        });
+
+        if payload_size as usize > mem::size_of::<Uf2BlockData>() {
+            report.problems.push(format!(
+                "Block {index}: payload size {payload_size} exceeds the 476 byte data region"
+            ));
+        }
+        if let Some((addr, len, digest)) = block.md5() {
+            // Only a checksum of the block's own payload can be checked without the flash
+            if addr == target_addr
+                && len == payload_size
+                && payload_size as usize <= mem::size_of::<Uf2BlockData>() - UF2_MD5_TRAILER_SIZE
+                && md5(&block.data[..payload_size as usize]) != digest
+            {
+                report
+                    .problems
+                    .push(format!("Block {index}: MD5 does not match the payload"));
+            }
+        }
+        if block_no >= num_blocks {
+            report.problems.push(format!(
+                "Block {index}: block number {block_no} is not below the block count {num_blocks}"
+            ));
+        }
+
+        let family = block.family_id();
+        let summary = report
+            .families
+            .entry(family)
+            .or_insert_with(|| Uf2FamilySummary {
+                num_blocks,
+                ..Default::default()
+            });
+
+        if summary.num_blocks != num_blocks {
+            report.problems.push(format!(
+                "Block {index}: block count {num_blocks} differs from the {} of earlier blocks",
+                summary.num_blocks
+            ));
+        }
+        let family_block_nos = block_nos.entry(family).or_default();
+        if family_block_nos.last().is_some_and(|&last| last > block_no) {
+            report.out_of_order = true;
+        }
+        if !family_block_nos.insert(block_no) {
+            report
+                .problems
+                .push(format!("Block {index}: duplicate block number {block_no}"));
+        }
+
+        summary.blocks += 1;
+        summary.payload_bytes += payload_size.min(476) as usize;
+    }
+
+    for (family, summary) in &report.families {
+        if summary.blocks != summary.num_blocks as usize {
+            report.problems.push(format!(
+                "Family {}: {} blocks present but the block count is {}",
+                family.map_or("none".to_string(), |id| format!("{id:#010x}")),
+                summary.blocks,
+                summary.num_blocks
+            ));
+        }
+    }
+
+    Ok(report)
+}
+
+/// Removes the blocks of a family and renumbers the remaining blocks within their own family.
+/// Returns how many blocks were removed
+pub fn drop_family(blocks: &mut Vec<Uf2Block>, family_id: u32) -> usize {
+    let before = blocks.len();
+    blocks.retain(|block| block.family_id() != Some(family_id));
+    renumber_by_family(blocks);
+
+    before - blocks.len()
+}
+
+/// Numbers the blocks of every family from zero in their order, with the count of the family
+fn renumber_by_family(blocks: &mut [Uf2Block]) {
+    let mut counts: BTreeMap<Option<u32>, u32> = BTreeMap::new();
+    for block in blocks.iter() {
+        *counts.entry(block.family_id()).or_default() += 1;
+    }
+
+    let mut numbers: BTreeMap<Option<u32>, u32> = BTreeMap::new();
+    for block in blocks.iter_mut() {
+        let family = block.family_id();
+        let block_no = numbers.entry(family).or_default();
+        block.header.block_no = *block_no;
+        block.header.num_blocks = counts[&family];
+        *block_no += 1;
+    }
+}
+
+/// Splits the blocks of a UF2 by family, each family numbered as a file of its own. Blocks
+/// without a family id are under `None`
+pub fn split_uf2_by_family(
+    input: impl Read,
+    magics: Uf2Magics,
+) -> Result<BTreeMap<Option<u32>, Vec<u8>>, Box<dyn Error>> {
+    let mut families: BTreeMap<Option<u32>, Vec<Uf2Block>> = BTreeMap::new();
+
+    for (index, block) in Uf2Reader::new(input).enumerate() {
+        let block = block?;
+        if block.magics() != magics {
+            return Err(format!("Block {index} has bad magic").into());
+        }
+        families.entry(block.family_id()).or_default().push(block);
+    }
+
+    Ok(families
+        .into_iter()
+        .map(|(family, mut blocks)| {
+            renumber_by_family(&mut blocks);
+            let bytes = blocks.iter().flat_map(|block| block.to_bytes()).collect();
+            (family, bytes)
+        })
+        .collect())
+}
+
+/// Writes the blocks of all `inputs` one after the other, numbered within their family like the
+/// blocks of a single file. Family ids and flags are kept, as the bootloader picks blocks by
+/// family, but blocks of one family from different inputs must not overlap. Returns the number
+/// of blocks written
+pub fn merge_uf2(
+    inputs: &mut [impl Read],
+    mut output: impl Write,
+    magics: Uf2Magics,
+) -> Result<usize, Box<dyn Error>> {
+    let mut blocks = Vec::new();
+    // Start address of every block by family, with its end and input
+    let mut spans: BTreeMap<(Option<u32>, u32), (u64, usize)> = BTreeMap::new();
+    let mut overlaps = Vec::new();
+
+    for (input_no, input) in inputs.iter_mut().enumerate() {
+        for (index, block) in Uf2Reader::new(input).enumerate() {
+            let block = block?;
+            if block.magics() != magics {
+                return Err(
+                    format!("Block {index} of input {} has bad magic", input_no + 1).into(),
+                );
+            }
+
+            let family = block.family_id();
+            let start = block.header.target_addr;
+            let payload_size = block.header.payload_size;
+            if payload_size > mem::size_of::<Uf2BlockData>().assert_into() {
+                return Err(format!(
+                    "Block {index} of input {} has a bad payload size {payload_size}",
+                    input_no + 1
+                )
+                .into());
+            }
+            let end = u64::from(start) + u64::from(payload_size);
+
+            // Blocks overlapping this one start at most a data region before it
+            let earliest = start.saturating_sub(mem::size_of::<Uf2BlockData>().assert_into());
+            let nearby =
+                spans
+                    .range((family, earliest)..)
+                    .take_while(|((other_family, other_start), _)| {
+                        *other_family == family && u64::from(*other_start) < end
+                    });
+            for (&(_, other_start), &(other_end, other_input)) in nearby {
+                if other_input != input_no && other_end > u64::from(start) {
+                    overlaps.push(format!(
+                        "{start:#010x} of input {} overlaps {other_start:#010x} of input {}",
+                        input_no + 1,
+                        other_input + 1
+                    ));
+                }
+            }
+            spans.insert((family, start), (end, input_no));
+
+            blocks.push(block);
+        }
+    }
+
+    if !overlaps.is_empty() {
+        const LISTED: usize = 4;
+        let more = match overlaps.len().saturating_sub(LISTED) {
+            0 => String::new(),
+            more => format!(" and {more} more"),
+        };
+        overlaps.truncate(LISTED);
+        return Err(format!("The inputs overlap: {}{more}", overlaps.join(", ")).into());
+    }
+
+    renumber_by_family(&mut blocks);
+
+    for block in &blocks {
+        output.write_all(&block.to_bytes())?;
+    }
+    output.flush()?;
+
+    Ok(blocks.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn dropped_family_is_renumbered() {
+        let block = |family_id: u32, block_no: u32| {
+            let mut block = Uf2Block::new();
+            block.header.flags = UF2_FLAG_FAMILY_ID_PRESENT;
+            block.header.file_size = family_id;
+            block.header.block_no = block_no;
+            block.header.num_blocks = 4;
+            block
+        };
+        let riscv = RP2350_ARM_S_FAMILY_ID + 1;
+
+        let mut blocks = vec![
+            block(RP2350_ARM_S_FAMILY_ID, 0),
+            block(riscv, 1),
+            block(RP2350_ARM_S_FAMILY_ID, 2),
+            block(riscv, 3),
+        ];
+        assert_eq!(drop_family(&mut blocks, riscv), 2);
+
+        let numbers: Vec<(u32, u32, u32)> = blocks
+            .iter()
+            .map(|block| {
+                (
+                    block.header.file_size,
+                    block.header.block_no,
+                    block.header.num_blocks,
+                )
+            })
+            .collect();
+        assert_eq!(
+            numbers,
+            [
+                (RP2350_ARM_S_FAMILY_ID, 0, 2),
+                (RP2350_ARM_S_FAMILY_ID, 1, 2)
+            ]
+        );
+
+        assert_eq!(drop_family(&mut blocks, riscv), 0);
+    }
+
+    #[test]
+    pub fn seekable_writer_patches_block_count() {
+        let riscv = RP2350_ARM_S_FAMILY_ID + 1;
+        let mut output = io::Cursor::new(vec![0xff; 7]);
+        output.set_position(7);
+
+        let mut writer = Uf2SeekableWriter::new(output).unwrap();
+        for (i, family_id) in (0..).zip([RP2350_ARM_S_FAMILY_ID, riscv, RP2350_ARM_S_FAMILY_ID]) {
+            let mut block = Uf2Block::new();
+            block.header.flags = UF2_FLAG_FAMILY_ID_PRESENT;
+            block.header.file_size = family_id;
+            block.header.target_addr = 0x10000000 + i * 256;
+            block.header.payload_size = 256;
+            writer.write_block(&mut block).unwrap();
+        }
+        assert_eq!(writer.blocks(), 3);
+
+        let mut output = writer.finish().unwrap();
+        assert_eq!(output.position(), 7 + 3 * UF2_BLOCK_SIZE as u64);
+        output.write_all(&[0xff; 7]).unwrap();
+        let uf2 = output.into_inner();
+
+        let numbers: Vec<(u32, u32, u32)> = uf2[7..7 + 3 * UF2_BLOCK_SIZE]
+            .chunks_exact(UF2_BLOCK_SIZE)
+            .map(|bytes| {
+                let block = Uf2Block::from_bytes(bytes.try_into().unwrap()).unwrap();
+                (
+                    block.header.file_size,
+                    block.header.block_no,
+                    block.header.num_blocks,
+                )
+            })
+            .collect();
+        assert_eq!(
+            numbers,
+            [
+                (RP2350_ARM_S_FAMILY_ID, 0, 2),
+                (riscv, 0, 1),
+                (RP2350_ARM_S_FAMILY_ID, 1, 2)
+            ]
+        );
+        assert_eq!(uf2[..7], [0xff; 7]);
+        assert_eq!(uf2[7 + 3 * UF2_BLOCK_SIZE..], [0xff; 7]);
+    }
+
+    #[test]
+    pub fn merged_inputs() {
+        let uf2 = |blocks: &[(u32, u32)]| {
+            let mut bytes = Vec::new();
+            for (i, &(family_id, target_addr)) in blocks.iter().enumerate() {
+                let mut block = Uf2Block::new();
+                block.header.flags = UF2_FLAG_FAMILY_ID_PRESENT;
+                block.header.file_size = family_id;
+                block.header.target_addr = target_addr;
+                block.header.payload_size = 256;
+                block.header.block_no = i.assert_into();
+                block.header.num_blocks = blocks.len().assert_into();
+                bytes.extend_from_slice(&block.to_bytes());
+            }
+            io::Cursor::new(bytes)
+        };
+        let riscv = RP2350_ARM_S_FAMILY_ID + 1;
+        let arm = RP2350_ARM_S_FAMILY_ID;
+
+        // A bootloader and an application, and the same application for the other architecture
+        let mut inputs = [
+            uf2(&[(arm, 0x10000000), (arm, 0x10000100)]),
+            uf2(&[(arm, 0x10010000)]),
+            uf2(&[(riscv, 0x10000000)]),
+        ];
+        let mut merged = Vec::new();
+        assert_eq!(
+            merge_uf2(&mut inputs, &mut merged, Uf2Magics::default()).unwrap(),
+            4
+        );
+        let report = validate_uf2(&merged[..], Uf2Magics::default()).unwrap();
+        assert!(report.is_valid(), "{:?}", report.problems);
+        assert_eq!(report.families[&Some(arm)].num_blocks, 3);
+        assert_eq!(report.families[&Some(riscv)].num_blocks, 1);
+
+        let mut inputs = [
+            uf2(&[(arm, 0x10000000), (arm, 0x10000100)]),
+            uf2(&[(arm, 0x10000180)]),
+        ];
+        let err = merge_uf2(&mut inputs, io::sink(), Uf2Magics::default()).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("0x10000180 of input 2 overlaps 0x10000100 of input 1"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    pub fn split_by_family() {
+        let block = |flags: u32, family_id: u32, block_no: u32| {
+            let mut block = Uf2Block::new();
+            block.header.flags = flags;
+            block.header.file_size = family_id;
+            block.header.block_no = block_no;
+            block.header.num_blocks = 4;
+            block.to_bytes()
+        };
+        let riscv = RP2350_ARM_S_FAMILY_ID + 1;
+        let arm = RP2350_ARM_S_FAMILY_ID;
+
+        let uf2 = [
+            block(UF2_FLAG_FAMILY_ID_PRESENT, arm, 0),
+            block(UF2_FLAG_FAMILY_ID_PRESENT, riscv, 1),
+            block(UF2_FLAG_FAMILY_ID_PRESENT, arm, 2),
+            // The file size of a block without a family id is no family
+            block(0, arm, 3),
+        ]
+        .concat();
+
+        let split = split_uf2_by_family(&uf2[..], Uf2Magics::default()).unwrap();
+        assert_eq!(
+            split.keys().copied().collect::<Vec<_>>(),
+            [None, Some(arm), Some(riscv)]
+        );
+        for (family, bytes) in &split {
+            let report = validate_uf2(&bytes[..], Uf2Magics::default()).unwrap();
+            assert!(report.is_valid(), "{:?}", report.problems);
+            assert_eq!(report.families.keys().collect::<Vec<_>>(), [family]);
+        }
+        assert_eq!(split[&Some(arm)].len(), 2 * UF2_BLOCK_SIZE);
+    }
+
+    #[test]
+    pub fn block_round_trip() {
+        let mut block = Uf2Block::new();
+        block.header.target_addr = 0x10000100;
+        block.header.payload_size = 256;
+        block.data[0] = 0xaa;
+
+        let bytes = block.to_bytes();
+        let read = Uf2Block::from_bytes(&bytes).unwrap();
+        assert_eq!({ read.header.target_addr }, 0x10000100);
+        assert_eq!(read.data[0], 0xaa);
+        assert_eq!(read.to_bytes(), bytes);
+
+        let mut corrupt = bytes;
+        corrupt[UF2_BLOCK_SIZE - 1] ^= 0xff;
+        assert!(Uf2Block::from_bytes(&corrupt).is_err());
+    }
+
+    #[test]
+    pub fn validate_collects_all_problems() {
+        let uf2 = include_bytes!("../hello_usb.uf2");
+
+        let report = validate_uf2(&uf2[..], Uf2Magics::default()).unwrap();
+        assert!(report.is_valid(), "{:?}", report.problems);
+        assert_eq!(report.blocks, uf2.len() / UF2_BLOCK_SIZE);
+        assert_eq!(
+            report.families[&Some(RP2040_FAMILY_ID)].blocks,
+            report.blocks
+        );
+        assert_eq!(report.block_summaries.len(), report.blocks);
+        assert_eq!(
+            report.block_summaries[1],
+            Uf2BlockSummary {
+                block_no: 1,
+                target_addr: 0x10000100,
+                payload_size: 256,
+                flags: UF2_FLAG_FAMILY_ID_PRESENT,
+            }
+        );
+        assert_eq!(
+            report.address_range,
+            Some(0x10000000..0x10000000 + 256 * report.blocks as u32)
+        );
+        assert!(!report.out_of_order);
+
+        let reversed: Vec<u8> = uf2
+            .chunks(UF2_BLOCK_SIZE)
+            .rev()
+            .flatten()
+            .copied()
+            .collect();
+        let report = validate_uf2(&reversed[..], Uf2Magics::default()).unwrap();
+        assert!(report.is_valid(), "{:?}", report.problems);
+        assert!(report.out_of_order);
+
+        let mut corrupt = uf2.to_vec();
+        let mut patch = |i: usize, f: fn(&mut Uf2Block)| {
+            let bytes = &mut corrupt[i * UF2_BLOCK_SIZE..(i + 1) * UF2_BLOCK_SIZE];
+            let mut block = Uf2Block::from_bytes((&*bytes).try_into().unwrap()).unwrap();
+            f(&mut block);
+            bytes.copy_from_slice(&block.to_bytes());
+        };
+        patch(1, |block| block.header.payload_size = 600);
+        patch(2, |block| block.header.block_no = 1000);
+        patch(3, |block| block.footer.magic_end = 0);
+
+        let report = validate_uf2(&corrupt[..], Uf2Magics::default()).unwrap();
+        assert_eq!(report.problems.len(), 4, "{:?}", report.problems);
+        assert!(report.problems[0].contains("payload size 600"));
+        assert!(report.problems[1].contains("block number 1000"));
+        assert!(report.problems[2].contains("invalid magic"));
+        // The block with the bad magic is missing from the count
+        assert!(report.problems[3].contains("blocks present"));
+
+        assert!(validate_uf2(&uf2[..100], Uf2Magics::default()).is_err());
+    }
+
+    #[test]
+    pub fn md5_trailer() {
+        let uf2 = include_bytes!("../hello_usb.uf2");
+        let mut blocks: Vec<Uf2Block> = Uf2Reader::new(&uf2[..]).collect::<Result<_, _>>().unwrap();
+        assert!(blocks[0].md5().is_none());
+
+        let mut with_md5 = Vec::new();
+        for block in &mut blocks {
+            block.set_md5();
+            with_md5.extend_from_slice(&block.to_bytes());
+        }
+
+        let (addr, len, digest) = blocks[1].md5().unwrap();
+        assert_eq!((addr, len), (0x10000100, 256));
+        assert_eq!(
+            digest,
+            md5(&uf2[UF2_BLOCK_SIZE + 32..UF2_BLOCK_SIZE + 32 + 256])
+        );
+
+        let report = validate_uf2(&with_md5[..], Uf2Magics::default()).unwrap();
+        assert!(report.is_valid(), "{:?}", report.problems);
+
+        with_md5[UF2_BLOCK_SIZE + 32] ^= 1;
+        let report = validate_uf2(&with_md5[..], Uf2Magics::default()).unwrap();
+        assert_eq!(report.problems.len(), 1, "{:?}", report.problems);
+        assert!(report.problems[0].contains("MD5"));
+    }
+
+    #[test]
+    pub fn custom_magics() {
+        let magics = Uf2Magics {
+            start0: 0x12345678,
+            ..Default::default()
+        };
+
+        let block = Uf2Block::with_magics(magics);
+        assert!(!block.has_valid_magic());
+        assert_eq!(block.magics(), magics);
+
+        let uf2 = include_bytes!("../hello_usb.uf2");
+        let report = validate_uf2(&uf2[..], magics).unwrap();
+        assert_eq!(report.problems.len(), report.blocks);
+        assert!(report.problems[0].contains("invalid magic"));
+    }
+}
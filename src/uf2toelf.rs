@@ -0,0 +1,197 @@
+//! Recovers an ELF from the blocks of a UF2
+
+use crate::{
+    elf::{Elf32Header, Elf32PhEntry, ELFCLASS32, ELF_MAGIC, EM_ARM, ET_EXEC, PT_LOAD},
+    uf2::{Uf2BlockData, Uf2Magics, Uf2Reader, UF2_FLAG_FILE_CONTAINER, UF2_FLAG_NOT_MAIN_FLASH},
+};
+use assert_into::AssertInto;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    io::{Read, Write},
+    mem,
+};
+use zerocopy::IntoBytes;
+
+const PF_X: u32 = 1;
+const PF_R: u32 = 4;
+
+/// Segments are written with this alignment between their address and file offset
+const SEGMENT_ALIGN: u32 = 4;
+
+/// Writes an ELF with a loadable segment for every run of contiguous blocks of a single family
+/// UF2. Blocks not meant for main flash are skipped, and the entry point is the lowest address
+/// with the Thumb bit set
+pub fn uf2toelf(
+    input: impl Read,
+    output: impl Write,
+    magics: Uf2Magics,
+) -> Result<(), Box<dyn Error>> {
+    let mut payloads: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+    let mut block_nos = BTreeSet::new();
+    let mut family = None;
+    let mut num_blocks = None;
+
+    for (index, block) in Uf2Reader::new(input).enumerate() {
+        let block = block?;
+        let header = block.header;
+
+        if block.magics() != magics {
+            return Err(format!("Block {index} has bad magic").into());
+        }
+        if header.flags & (UF2_FLAG_NOT_MAIN_FLASH | UF2_FLAG_FILE_CONTAINER) != 0 {
+            continue;
+        }
+
+        let block_family = block.family_id();
+        if *family.get_or_insert(block_family) != block_family {
+            return Err(
+                "The UF2 has blocks of several families, drop all but one with drop-family".into(),
+            );
+        }
+
+        let block_no = header.block_no;
+        if *num_blocks.get_or_insert(header.num_blocks) != header.num_blocks {
+            return Err(format!(
+                "Block {block_no} has a block count of {}, other blocks have {}",
+                { header.num_blocks },
+                num_blocks.unwrap()
+            )
+            .into());
+        }
+        if !block_nos.insert(block_no) {
+            return Err(format!("Block number {block_no} appears twice").into());
+        }
+
+        let payload_size = header.payload_size;
+        if payload_size > mem::size_of::<Uf2BlockData>().assert_into() {
+            return Err(format!("Block {block_no} has a bad payload size {payload_size}").into());
+        }
+        payloads.insert(
+            header.target_addr,
+            block.data[..payload_size.assert_into()].to_vec(),
+        );
+    }
+
+    // Blocks may come in any order, the segments are built in address order
+    let mut segments: Vec<(u32, Vec<u8>)> = Vec::new();
+    for (addr, payload) in payloads {
+        match segments.last_mut() {
+            Some((start, data)) if (*start as u64 + data.len() as u64) > addr as u64 => {
+                return Err(format!("The block at {addr:#08x} overlaps the one before").into());
+            }
+            Some((start, data)) if *start as u64 + data.len() as u64 == addr as u64 => {
+                data.extend(payload)
+            }
+            _ => segments.push((addr, payload)),
+        }
+    }
+
+    if segments.is_empty() {
+        return Err("The UF2 has no blocks for main flash".into());
+    }
+
+    write_elf(&segments, output)
+}
+
+/// Writes an Arm executable with a loadable segment for each of `segments`, given as address and
+/// contents in address order. The entry point is the first address with the Thumb bit set
+pub fn write_elf(
+    segments: &[(u32, Vec<u8>)],
+    mut output: impl Write,
+) -> Result<(), Box<dyn Error>> {
+    let (first_addr, _) = segments
+        .first()
+        .ok_or("An ELF needs at least one segment")?;
+
+    let mut eh = Elf32Header::default();
+    eh.common.magic = ELF_MAGIC;
+    eh.common.arch_class = ELFCLASS32;
+    eh.common.endianness = 1;
+    eh.common.version = 1;
+    eh.common.version2 = 1;
+    eh.common.typ = ET_EXEC;
+    eh.common.machine = EM_ARM;
+    eh.entry = first_addr | 1;
+    eh.ph_offset = mem::size_of::<Elf32Header>().assert_into();
+    eh.eh_size = mem::size_of::<Elf32Header>().assert_into();
+    eh.ph_entry_size = mem::size_of::<Elf32PhEntry>().assert_into();
+    eh.ph_num = segments
+        .len()
+        .try_into()
+        .map_err(|_| "The UF2 has too many segments for an ELF")?;
+
+    let mut offset: u32 = eh.ph_offset + u32::from(eh.ph_num) * u32::from(eh.ph_entry_size);
+    let mut entries = Vec::new();
+    for (addr, data) in segments {
+        offset += (addr.wrapping_sub(offset)) % SEGMENT_ALIGN;
+        let size: u32 = data.len().assert_into();
+        entries.push(Elf32PhEntry {
+            typ: PT_LOAD,
+            offset,
+            vaddr: *addr,
+            paddr: *addr,
+            filez: size,
+            memsz: size,
+            flags: PF_R | PF_X,
+            align: SEGMENT_ALIGN,
+        });
+        offset += size;
+    }
+
+    output.write_all(eh.as_mut_bytes())?;
+    output.write_all(entries.as_mut_slice().as_mut_bytes())?;
+
+    let mut written = eh.ph_offset + u32::from(eh.ph_num) * u32::from(eh.ph_entry_size);
+    for (entry, (_, data)) in entries.iter().zip(segments) {
+        let padding: usize = (entry.offset - written).assert_into();
+        output.write_all(&[0; SEGMENT_ALIGN as usize][..padding])?;
+        output.write_all(data)?;
+        written = entry.offset + entry.filez;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{boards::RP2040, elf2uf2, progress::NoProgress, ConversionOptions};
+    use std::io;
+
+    #[test]
+    pub fn hello_usb_round_trip() {
+        let uf2 = &include_bytes!("../hello_usb.uf2")[..];
+        let mut elf = Vec::new();
+        uf2toelf(uf2, &mut elf, Uf2Magics::default()).unwrap();
+
+        // The recovered ELF converts back to the same blocks
+        let mut bytes_out = Vec::new();
+        elf2uf2(
+            io::Cursor::new(elf),
+            &mut bytes_out,
+            &mut NoProgress,
+            &RP2040,
+            &ConversionOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(bytes_out, uf2);
+    }
+
+    #[test]
+    pub fn blocks_out_of_order() {
+        let uf2 = include_bytes!("../hello_usb.uf2");
+        let mut blocks: Vec<&[u8]> = uf2.chunks(512).collect();
+        blocks.reverse();
+
+        let mut in_order = Vec::new();
+        uf2toelf(&uf2[..], &mut in_order, Uf2Magics::default()).unwrap();
+        let mut reversed = Vec::new();
+        uf2toelf(&blocks.concat()[..], &mut reversed, Uf2Magics::default()).unwrap();
+        assert_eq!(in_order, reversed);
+
+        blocks.push(blocks[0]);
+        let err = uf2toelf(&blocks.concat()[..], io::sink(), Uf2Magics::default()).unwrap_err();
+        assert!(err.to_string().contains("appears twice"), "{err}");
+    }
+}
@@ -0,0 +1,119 @@
+use crate::boards::{BoardInfo, BoardIter, UsbDevice};
+use std::{error::Error, time::Duration};
+
+/// A connected USB device and the board it was recognized as, if any
+pub type ConnectedDevice = (UsbDevice, Option<Box<dyn BoardInfo>>);
+
+/// Enumerates connected USB devices and matches each against the known boards
+pub fn list_devices() -> Result<Vec<ConnectedDevice>, Box<dyn Error>> {
+    let mut devices = Vec::new();
+
+    for device in rusb::devices()?.iter() {
+        let descriptor = device.device_descriptor()?;
+
+        let usb_device = UsbDevice {
+            bus_number: device.bus_number(),
+            address: device.address(),
+            vendor_id: descriptor.vendor_id(),
+            product_id: descriptor.product_id(),
+        };
+
+        let board = BoardIter::new().find(|board| board.is_device_board(&usb_device));
+
+        devices.push((usb_device, board));
+    }
+
+    Ok(devices)
+}
+
+/// The first known board connected in bootloader mode
+pub fn detect_connected_board() -> Result<Option<Box<dyn BoardInfo>>, Box<dyn Error>> {
+    Ok(list_devices()?.into_iter().find_map(|(_, board)| board))
+}
+
+const PICOBOOT_MAGIC: u32 = 0x431fd10b;
+const PICOBOOT_CMD_REBOOT: u8 = 0x02;
+/// The RP2350 bootrom only knows this reboot command
+const PICOBOOT_CMD_REBOOT2: u8 = 0x0a;
+const PICOBOOT_INTERFACE_CLASS: u8 = 0xff;
+const PICOBOOT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Reboots every known board still in bootloader mode into its application with a PICOBOOT
+/// reboot command. Returns the number of boards rebooted
+pub fn reboot_bootloader_devices() -> Result<usize, Box<dyn Error>> {
+    let mut rebooted = 0;
+
+    for (usb_device, board) in list_devices()? {
+        let Some(board) = board else {
+            continue;
+        };
+
+        let device = rusb::devices()?
+            .iter()
+            .find(|device| {
+                device.bus_number() == usb_device.bus_number
+                    && device.address() == usb_device.address
+            })
+            .ok_or("The device disconnected")?;
+
+        picoboot_reboot(&device, board.name() == "rp2350")?;
+        rebooted += 1;
+    }
+
+    Ok(rebooted)
+}
+
+fn picoboot_reboot(
+    device: &rusb::Device<rusb::GlobalContext>,
+    reboot2: bool,
+) -> Result<(), Box<dyn Error>> {
+    let config = device.active_config_descriptor()?;
+
+    let (interface, endpoint_out, endpoint_in) = config
+        .interfaces()
+        .flat_map(|interface| interface.descriptors())
+        .filter(|descriptor| descriptor.class_code() == PICOBOOT_INTERFACE_CLASS)
+        .find_map(|descriptor| {
+            let bulk = |direction| {
+                descriptor
+                    .endpoint_descriptors()
+                    .find(|endpoint| {
+                        endpoint.transfer_type() == rusb::TransferType::Bulk
+                            && endpoint.direction() == direction
+                    })
+                    .map(|endpoint| endpoint.address())
+            };
+            Some((
+                descriptor.interface_number(),
+                bulk(rusb::Direction::Out)?,
+                bulk(rusb::Direction::In)?,
+            ))
+        })
+        .ok_or("The device has no PICOBOOT interface")?;
+
+    let handle = device.open()?;
+    handle.claim_interface(interface)?;
+
+    let delay_ms: u32 = 100;
+    let mut command = [0; 32];
+    command[0..4].copy_from_slice(&PICOBOOT_MAGIC.to_le_bytes());
+    command[4..8].copy_from_slice(&1u32.to_le_bytes());
+    if reboot2 {
+        // struct picoboot_cmd with a picoboot_reboot2_cmd, zero flags reboot normally
+        command[8] = PICOBOOT_CMD_REBOOT2;
+        command[9] = 16;
+        command[20..24].copy_from_slice(&delay_ms.to_le_bytes());
+    } else {
+        // struct picoboot_cmd with a picoboot_reboot_cmd, a zero pc and sp reboot normally
+        command[8] = PICOBOOT_CMD_REBOOT;
+        command[9] = 12;
+        command[24..28].copy_from_slice(&delay_ms.to_le_bytes());
+    }
+
+    handle.write_bulk(endpoint_out, &command, PICOBOOT_TIMEOUT)?;
+
+    // Commands without data are acknowledged with an empty packet
+    handle.read_bulk(endpoint_in, &mut [0; 64], PICOBOOT_TIMEOUT)?;
+
+    Ok(())
+}